@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE subscription_tokens
+    ADD COLUMN issued_at  timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at timestamptz NULL;
+-- existing tokens get a day from now before they lapse
+UPDATE subscription_tokens
+SET expires_at = issued_at + interval '1 day'
+WHERE expires_at IS NULL;
+ALTER TABLE subscription_tokens
+    ALTER COLUMN expires_at SET NOT NULL;
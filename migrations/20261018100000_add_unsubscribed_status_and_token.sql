@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Statuses are written through `SubscriberStatus`; keep the column honest.
+ALTER TABLE subscriptions
+    ADD CONSTRAINT subscriptions_status_check
+        CHECK (status IN ('pending_confirmation', 'confirmed', 'unsubscribed'));
+
+ALTER TABLE subscriptions
+    ADD COLUMN unsubscribe_token TEXT NULL UNIQUE;
+-- backfill existing subscribers; new rows get an application-generated token
+UPDATE subscriptions
+SET unsubscribe_token = md5(random()::text || clock_timestamp()::text || id::text)
+WHERE unsubscribe_token IS NULL;
+ALTER TABLE subscriptions
+    ALTER COLUMN unsubscribe_token SET NOT NULL;
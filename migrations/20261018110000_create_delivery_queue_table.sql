@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE delivery_queue
+(
+    id              uuid        NOT NULL,
+    PRIMARY KEY (id),
+    recipient_email TEXT        NOT NULL,
+    subject         TEXT        NOT NULL,
+    html_body       TEXT        NOT NULL,
+    text_body       TEXT        NOT NULL,
+    n_retries       INT         NOT NULL DEFAULT 0,
+    execute_after   timestamptz NOT NULL DEFAULT now(),
+    created_at      timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX delivery_queue_execute_after_idx ON delivery_queue (execute_after);
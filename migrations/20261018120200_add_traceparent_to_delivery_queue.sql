@@ -0,0 +1,4 @@
+-- Add migration script here
+-- W3C trace context of the request that queued the email, so the eventual
+-- provider call joins the same trace.
+ALTER TABLE delivery_queue ADD COLUMN traceparent TEXT NULL;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- The canonical form identifies a mailbox; `email` keeps the address as
+-- typed, for sending. Existing rows are left NULL and filled in at startup
+-- by `canonical_emails::backfill_canonical_emails`, which canonicalizes the
+-- same way new subscriptions are (punycode domains, configured local part
+-- handling). Rows whose address no longer parses stay NULL.
+ALTER TABLE subscriptions ADD COLUMN email_canonical TEXT NULL;
+ALTER TABLE subscriptions
+    ADD CONSTRAINT subscriptions_email_canonical_key UNIQUE (email_canonical);
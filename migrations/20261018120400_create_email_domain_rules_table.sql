@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Admin-managed additions to the bundled blocklist. An `allow` rule lets a
+-- domain through even if it is blocked elsewhere.
+CREATE TABLE email_domain_rules (
+    domain TEXT NOT NULL,
+    PRIMARY KEY (domain),
+    rule TEXT NOT NULL CHECK (rule IN ('block', 'allow')),
+    created_at timestamptz NOT NULL DEFAULT now()
+);
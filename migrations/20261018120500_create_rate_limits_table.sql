@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE rate_limits
+(
+    key            TEXT        NOT NULL,
+    PRIMARY KEY (key),
+    hits           INT         NOT NULL,
+    window_ends_at timestamptz NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- Tokens are looked up by their SHA-256 from now on. Hashing the stored
+-- values in place keeps links already sent out working.
+ALTER TABLE subscription_tokens
+    RENAME COLUMN subscription_token TO token_hash;
+UPDATE subscription_tokens
+SET token_hash = encode(sha256(convert_to(token_hash, 'UTF8')), 'hex');
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- Set when a token confirms its subscriber; a used token never confirms again.
+ALTER TABLE subscription_tokens ADD COLUMN used_at timestamptz NULL;
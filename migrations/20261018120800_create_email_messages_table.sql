@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Every email we send, so that provider events (delivery, bounce, ...) can
+-- be joined back to the subscriber it went to. A queued email shares its id
+-- with its `delivery_queue` task.
+CREATE TABLE email_messages
+(
+    id                  uuid        NOT NULL,
+    PRIMARY KEY (id),
+    recipient_email     TEXT        NOT NULL,
+    template            TEXT        NOT NULL,
+    subscriber_id       uuid        NULL
+        REFERENCES subscriptions (id) ON DELETE SET NULL,
+    provider_message_id TEXT        NULL UNIQUE,
+    status              TEXT        NOT NULL
+        CHECK (status IN ('queued', 'sent', 'failed')),
+    created_at          timestamptz NOT NULL DEFAULT now(),
+    updated_at          timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX email_messages_subscriber_id_idx ON email_messages (subscriber_id);
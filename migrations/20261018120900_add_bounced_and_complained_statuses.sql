@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Set from provider webhooks when an address hard-bounces or complains.
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_status_check;
+ALTER TABLE subscriptions
+    ADD CONSTRAINT subscriptions_status_check
+        CHECK (status IN (
+            'pending_confirmation', 'confirmed', 'unsubscribed',
+            'bounced', 'complained'
+        ));
+
+ALTER TABLE email_messages DROP CONSTRAINT email_messages_status_check;
+ALTER TABLE email_messages
+    ADD CONSTRAINT email_messages_status_check
+        CHECK (status IN (
+            'queued', 'sent', 'failed', 'suppressed',
+            'delivered', 'bounced', 'complained'
+        ));
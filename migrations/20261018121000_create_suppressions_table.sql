@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Addresses we must not email, by canonical form. Checked before every send.
+CREATE TABLE suppressions
+(
+    email_canonical TEXT        NOT NULL,
+    PRIMARY KEY (email_canonical),
+    reason          TEXT        NOT NULL CHECK (reason IN ('bounce', 'complaint')),
+    created_at      timestamptz NOT NULL DEFAULT now()
+);
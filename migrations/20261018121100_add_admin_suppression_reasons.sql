@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Admins can suppress an address themselves, e.g. on a legal request.
+ALTER TABLE suppressions DROP CONSTRAINT suppressions_reason_check;
+ALTER TABLE suppressions
+    ADD CONSTRAINT suppressions_reason_check
+        CHECK (reason IN ('bounce', 'complaint', 'manual', 'legal_request'));
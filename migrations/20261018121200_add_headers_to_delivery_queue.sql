@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Extra headers to send the email with, as a JSON array of
+-- {"name": ..., "value": ...} objects.
+ALTER TABLE delivery_queue ADD COLUMN headers JSONB NOT NULL DEFAULT '[]';
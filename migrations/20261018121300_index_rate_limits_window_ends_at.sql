@@ -0,0 +1,3 @@
+-- Add migration script here
+-- For the periodic sweep of expired windows.
+CREATE INDEX rate_limits_window_ends_at_idx ON rate_limits (window_ends_at);
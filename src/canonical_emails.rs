@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{EmailNormalization, SubscriberEmail};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Runs `backfill_canonical_emails` once at startup, in the background so
+/// the server can start while the database is unreachable.
+pub struct CanonicalEmailBackfill {
+    db_pool: PgPool,
+    normalization: EmailNormalization,
+}
+
+impl CanonicalEmailBackfill {
+    pub fn new(db_pool: PgPool, normalization: EmailNormalization) -> Self {
+        Self {
+            db_pool,
+            normalization,
+        }
+    }
+
+    /// Retry until the backfill succeeds once.
+    pub async fn run_until_done(self) {
+        loop {
+            match backfill_canonical_emails(&self.db_pool, self.normalization)
+                .await
+            {
+                Ok(_) => return,
+                Err(e) => {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "Failed to backfill canonical emails",
+                    );
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                }
+            }
+        }
+    }
+}
+
+/// Fill in `email_canonical` for subscriptions stored before it existed.
+///
+/// This runs in Rust rather than in the migration so that old rows get
+/// exactly the canonical form `SubscriberEmail::parse_with` gives new ones.
+/// Where two addresses turn out to be the same mailbox, one row is kept: a
+/// confirmed one if there is one, otherwise the oldest. This holds against
+/// rows `subscribe` inserted since startup too, as the backfill runs while
+/// the server is serving. Addresses that no longer parse are left as they
+/// are. Returns the number of rows updated.
+#[tracing::instrument(name = "Backfilling canonical emails", skip(pool))]
+pub async fn backfill_canonical_emails(
+    pool: &PgPool,
+    normalization: EmailNormalization,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, email, status = 'confirmed' AS "confirmed!", subscribed_at
+        FROM subscriptions
+        WHERE email_canonical IS NULL
+        ORDER BY (status = 'confirmed') DESC, subscribed_at
+        FOR UPDATE
+        "#,
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    let mut updated = 0;
+    for row in rows {
+        let email = match SubscriberEmail::parse_with(row.email, normalization)
+        {
+            Ok(email) => email,
+            Err(error) => {
+                tracing::warn!(
+                    subscriber_id = %row.id,
+                    error.message = %error,
+                    "Leaving a stored address without a canonical form. \
+                    It does not parse.",
+                );
+                continue;
+            }
+        };
+        let holder = sqlx::query!(
+            r#"
+            SELECT id, status = 'confirmed' AS "confirmed!", subscribed_at
+            FROM subscriptions
+            WHERE email_canonical = $1
+            FOR UPDATE
+            "#,
+            email.canonical(),
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        if let Some(holder) = holder {
+            // Confirmed sorts before unconfirmed, then oldest first.
+            let keep_row = (!row.confirmed, row.subscribed_at)
+                < (!holder.confirmed, holder.subscribed_at);
+            let duplicate = if keep_row { holder.id } else { row.id };
+            tracing::info!(
+                subscriber_id = %duplicate,
+                "Deleting a subscription for a mailbox that is already \
+                subscribed.",
+            );
+            delete_subscription(&mut transaction, duplicate).await?;
+            if !keep_row {
+                continue;
+            }
+        }
+        sqlx::query!(
+            r#"UPDATE subscriptions SET email_canonical = $2 WHERE id = $1"#,
+            row.id,
+            email.canonical(),
+        )
+        .execute(&mut transaction)
+        .await?;
+        updated += 1;
+    }
+    transaction.commit().await?;
+    Ok(updated)
+}
+
+async fn delete_subscription(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut *transaction)
+        .await?;
+    Ok(())
+}
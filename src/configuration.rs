@@ -1,14 +1,53 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{
+    EmailNormalization, SubscriberEmail, SubscriberEmailError,
+};
+use crate::domain_filter::DomainFilter;
+use crate::email_client::{
+    EmailClient, OutboxEmailClient, PostmarkEmailClient, RetryPolicy,
+    SmtpEmailClient,
+};
+use crate::rate_limit::{Quota, RateLimitBackendKind};
+use crate::session_store::SessionStoreKind;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub metrics: MetricsSettings,
+    pub telemetry: TelemetrySettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TelemetrySettings {
+    pub service_name: String,
+    /// Base URL of an OTLP/HTTP collector, e.g. `http://localhost:4318`.
+    /// Traces are not exported when unset.
+    pub otlp_endpoint: Option<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub otlp_timeout_ms: u64,
+}
+
+impl TelemetrySettings {
+    pub fn otlp_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.otlp_timeout_ms)
+    }
+}
+
+/// Where `/metrics` is served. Kept off the application listener so it is
+/// not exposed alongside the public routes.
+#[derive(serde::Deserialize, Clone)]
+pub struct MetricsSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub host: String,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -17,6 +56,87 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub token_ttl_secs: u64,
+    /// Signs session and flash message cookies. At least 64 bytes.
+    pub hmac_secret: Secret<String>,
+    /// Signs one-click unsubscribe URLs, which stay valid for as long as
+    /// this key does. At least 32 bytes.
+    pub unsubscribe_signing_key: Secret<String>,
+    pub session_store: SessionStoreKind,
+    #[serde(default)]
+    pub email_normalization: EmailNormalization,
+    pub domain_filter: DomainFilterSettings,
+    pub rate_limit: RateLimitSettings,
+    /// Overrides for the templates embedded in the binary, laid out as in
+    /// `templates/`.
+    #[serde(default)]
+    pub templates_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub confirmation_redirects: ConfirmationRedirects,
+    pub webhooks: WebhookSettings,
+}
+
+/// The HTTP basic auth credentials email providers must present when
+/// calling `/webhooks/*`. Postmark sends them from the webhook URL.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// Where to send subscribers after they follow a confirmation link, by
+/// outcome. Outcomes without a URL render the page from `templates/`.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct ConfirmationRedirects {
+    pub confirmed: Option<String>,
+    pub already_confirmed: Option<String>,
+    pub expired: Option<String>,
+    pub invalid: Option<String>,
+}
+
+/// Quotas on the public routes that send email or check tokens.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    pub backend: RateLimitBackendKind,
+    /// Count requests against the address in this header rather than the
+    /// peer. Only set it behind a proxy that overwrites the header, since
+    /// clients can forge it.
+    #[serde(default)]
+    pub client_ip_header: Option<String>,
+    pub subscribe_per_ip: Quota,
+    pub subscribe_per_email: Quota,
+    pub confirm_per_ip: Quota,
+}
+
+/// Additions to the bundled blocklist, and exceptions to it. Further rules
+/// are managed at runtime through `/admin/email-domains`.
+#[derive(serde::Deserialize, Clone)]
+pub struct DomainFilterSettings {
+    #[serde(default)]
+    pub blocked_domains: Vec<String>,
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// How often to pick up rules changed through another instance.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub reload_interval_secs: u64,
+}
+
+impl DomainFilterSettings {
+    pub fn filter(&self) -> DomainFilter {
+        DomainFilter::new(&self.blocked_domains, &self.allowed_domains)
+    }
+
+    pub fn reload_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.reload_interval_secs)
+    }
+}
+
+impl ApplicationSettings {
+    /// How long a subscription confirmation link stays valid.
+    pub fn token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.token_ttl_secs)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -50,6 +170,7 @@ impl DatabaseSettings {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Environment {
     Local,
     Production,
@@ -79,16 +200,121 @@ impl TryFrom<String> for Environment {
     }
 }
 
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailProvider {
+    Postmark,
+    Smtp,
+    Outbox,
+}
+
+impl EmailProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailProvider::Postmark => "postmark",
+            EmailProvider::Smtp => "smtp",
+            EmailProvider::Outbox => "outbox",
+        }
+    }
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    pub provider: EmailProvider,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_ms: u64,
+    pub retry: RetrySettings,
+    /// Whether `/health/ready` probes the provider.
+    #[serde(default)]
+    pub readiness_check: ReadinessCheck,
+    pub smtp: Option<SmtpSettings>,
+    /// Where the outbox provider writes emails. Kept in memory only if unset.
+    pub outbox_dir: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessCheck {
+    /// Not probed at all.
+    #[default]
+    Skip,
+    /// Probed and reported, but a failure does not fail readiness.
+    Optional,
+    /// Probed, and a failure fails readiness.
+    Required,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RetrySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_ms: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_ms: u64,
+}
+
+impl RetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            base_delay: std::time::Duration::from_millis(self.base_delay_ms),
+            max_delay: std::time::Duration::from_millis(self.max_delay_ms),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub require_tls: bool,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
 }
 
 impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self
+            .sender()
+            .expect("Invalid sender email address in config.");
+        let timeout = self.timeout();
+        match self.provider {
+            EmailProvider::Postmark => Arc::new(PostmarkEmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+                self.retry.policy(),
+            )),
+            EmailProvider::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The smtp provider requires an `smtp` section.");
+                let credentials = smtp.username.zip(smtp.password);
+                Arc::new(
+                    SmtpEmailClient::new(
+                        &smtp.host,
+                        smtp.port,
+                        smtp.require_tls,
+                        credentials,
+                        sender_email,
+                        timeout,
+                    )
+                    .expect("Invalid SMTP settings in config."),
+                )
+            }
+            EmailProvider::Outbox => Arc::new(OutboxEmailClient::new(
+                sender_email,
+                self.outbox_dir.map(PathBuf::from),
+            )),
+        }
+    }
+
+    pub fn sender(&self) -> Result<SubscriberEmail, SubscriberEmailError> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
@@ -118,5 +344,98 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .separator("__"),
         )
         .build()?;
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    settings.validate(environment)?;
+    Ok(settings)
+}
+
+/// The shortest `hmac_secret` a cookie key can be derived from.
+const MIN_HMAC_SECRET_BYTES: usize = 64;
+
+/// The shortest `unsubscribe_signing_key`: the output size of HMAC-SHA256.
+const MIN_UNSUBSCRIBE_SIGNING_KEY_BYTES: usize = 32;
+
+/// The webhook password committed in `config/local.yaml`.
+const LOCAL_WEBHOOK_PASSWORD: &str = "local-webhook-password";
+
+impl Settings {
+    /// Checks that deserializing cannot express, so a bad configuration
+    /// stops the application at startup rather than on first use.
+    pub fn validate(
+        &self,
+        environment: Environment,
+    ) -> Result<(), config::ConfigError> {
+        let hmac_secret = self.application.hmac_secret.expose_secret();
+        if hmac_secret.len() < MIN_HMAC_SECRET_BYTES {
+            return Err(config::ConfigError::Message(format!(
+                "application.hmac_secret must be at least {} bytes long, \
+                got {}.",
+                MIN_HMAC_SECRET_BYTES,
+                hmac_secret.len()
+            )));
+        }
+        let signing_key =
+            self.application.unsubscribe_signing_key.expose_secret();
+        if signing_key.len() < MIN_UNSUBSCRIBE_SIGNING_KEY_BYTES {
+            return Err(config::ConfigError::Message(format!(
+                "application.unsubscribe_signing_key must be at least {} \
+                bytes long, got {}.",
+                MIN_UNSUBSCRIBE_SIGNING_KEY_BYTES,
+                signing_key.len()
+            )));
+        }
+        if environment == Environment::Production
+            && self.application.webhooks.password.expose_secret()
+                == LOCAL_WEBHOOK_PASSWORD
+        {
+            return Err(config::ConfigError::Message(
+                "application.webhooks.password is the committed local \
+                default. Set APP_APPLICATION__WEBHOOKS__PASSWORD."
+                    .into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::{get_configuration, Environment};
+
+    #[test]
+    fn the_local_configuration_is_valid() {
+        let settings = get_configuration();
+
+        assert_ok!(settings);
+    }
+
+    #[test]
+    fn a_short_hmac_secret_is_rejected() {
+        let mut settings = get_configuration().unwrap();
+        settings.application.hmac_secret = Secret::new("too short".into());
+
+        assert_err!(settings.validate(Environment::Local));
+    }
+
+    #[test]
+    fn a_short_unsubscribe_signing_key_is_rejected() {
+        let mut settings = get_configuration().unwrap();
+        settings.application.unsubscribe_signing_key =
+            Secret::new("too short".into());
+
+        assert_err!(settings.validate(Environment::Local));
+    }
+
+    #[test]
+    fn the_committed_webhook_password_is_rejected_in_production() {
+        let mut settings = get_configuration().unwrap();
+
+        assert_err!(settings.validate(Environment::Production));
+        settings.application.webhooks.password =
+            Secret::new("set-from-the-environment".into());
+        assert_ok!(settings.validate(Environment::Production));
+    }
 }
@@ -0,0 +1,410 @@
+use std::time::Duration;
+
+use sqlx::types::Json;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use tracing::{Instrument, Span};
+use uuid::Uuid;
+
+use crate::domain::{EmailNormalization, SubscriberEmail};
+use crate::email_client::{
+    EmailClient, EmailError, EmailHeader, OutgoingEmail, SendReceipt,
+};
+use crate::telemetry::{
+    add_remote_link, current_traceparent, set_remote_parent,
+};
+use crate::templates::{EmailTemplate, RenderedEmail};
+
+/// Attempts (including the first) before a delivery is given up on.
+const MAX_DELIVERY_ATTEMPTS: i32 = 5;
+/// Due emails claimed at once and handed to `send_batch` together.
+const BATCH_SIZE: i64 = 100;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct DeliveryWorker {
+    db_pool: PgPool,
+    email_client: EmailClient,
+    normalization: EmailNormalization,
+}
+
+impl DeliveryWorker {
+    pub fn new(
+        db_pool: PgPool,
+        email_client: EmailClient,
+        normalization: EmailNormalization,
+    ) -> Self {
+        Self {
+            db_pool,
+            email_client,
+            normalization,
+        }
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        loop {
+            let outcome = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                self.normalization,
+            )
+            .await;
+            match outcome {
+                Ok(ExecutionOutcome::EmptyQueue) => {
+                    tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+                }
+                Ok(ExecutionOutcome::TaskCompleted) => {}
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Where an email is in its life, as recorded in `email_messages`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailMessageStatus {
+    Queued,
+    Sent,
+    Failed,
+    /// Not sent: the recipient is on the suppression list.
+    Suppressed,
+    /// The following are reported by the provider after sending.
+    Delivered,
+    Bounced,
+    Complained,
+}
+
+impl EmailMessageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailMessageStatus::Queued => "queued",
+            EmailMessageStatus::Sent => "sent",
+            EmailMessageStatus::Failed => "failed",
+            EmailMessageStatus::Suppressed => "suppressed",
+            EmailMessageStatus::Delivered => "delivered",
+            EmailMessageStatus::Bounced => "bounced",
+            EmailMessageStatus::Complained => "complained",
+        }
+    }
+}
+
+/// Queue an email for the delivery worker and record it in
+/// `email_messages`. Pass the same transaction that writes the state the
+/// email describes, so both commit or neither does. `headers` are sent on
+/// top of those the provider sets.
+#[tracing::instrument(
+    name = "Enqueue email for delivery",
+    skip(executor, email, headers),
+    fields(template = template.as_str())
+)]
+pub async fn enqueue_email<'e>(
+    executor: impl PgExecutor<'e>,
+    recipient: &SubscriberEmail,
+    subscriber_id: Uuid,
+    template: EmailTemplate,
+    email: &RenderedEmail,
+    headers: &[EmailHeader],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH message AS (
+            INSERT INTO email_messages
+                (id, recipient_email, template, subscriber_id, status)
+            VALUES ($1, $2, $3, $4, $5)
+        )
+        INSERT INTO delivery_queue
+            (id, recipient_email, subject, html_body, text_body, traceparent,
+            headers)
+        VALUES ($1, $2, $6, $7, $8, $9, $10)
+        "#,
+        Uuid::new_v4(),
+        recipient.as_ref(),
+        template.as_str(),
+        subscriber_id,
+        EmailMessageStatus::Queued.as_str(),
+        email.subject,
+        email.html,
+        email.text,
+        current_traceparent(),
+        Json(headers) as _,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Claim up to `BATCH_SIZE` due emails and send them with one call to
+/// `send_batch`, recording each recipient's outcome.
+#[tracing::instrument(
+    skip_all,
+    fields(n_tasks = tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    normalization: EmailNormalization,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let tasks = dequeue_tasks(&mut transaction).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+
+    let mut to_send = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let recipient = task.recipient_email.clone();
+        match SubscriberEmail::parse_with(recipient, normalization) {
+            Ok(recipient) => to_send.push((task, recipient)),
+            Err(error) => {
+                tracing::error!(
+                    task_id = %task.id,
+                    error.message = %error,
+                    "Dropping a queued email. The recipient address is invalid.",
+                );
+                let status = EmailMessageStatus::Failed;
+                update_message(&mut transaction, task.id, status, None).await?;
+                delete_task(&mut transaction, task.id).await?;
+            }
+        }
+    }
+
+    // The provider call continues the trace of the request that queued the
+    // first email, and links to those of the others.
+    let send_span =
+        tracing::info_span!("Deliver queued emails", n_emails = to_send.len());
+    let mut traceparents = to_send
+        .iter()
+        .filter_map(|(task, _)| task.traceparent.as_deref());
+    if let Some(traceparent) = traceparents.next() {
+        set_remote_parent(&send_span, traceparent);
+    }
+    for traceparent in traceparents {
+        add_remote_link(&send_span, traceparent);
+    }
+    let (tasks, emails): (Vec<_>, Vec<_>) = to_send
+        .into_iter()
+        .map(|(task, recipient)| {
+            // Lets the provider's events about the email be matched to its
+            // `email_messages` row.
+            let email = OutgoingEmail::new(
+                recipient,
+                task.subject.as_str(),
+                task.html_body.as_str(),
+                task.text_body.as_str(),
+            )
+            .with_headers(task.headers.0.clone())
+            .with_metadata("email_message_id", task.id.to_string());
+            (task, email)
+        })
+        .unzip();
+    let outcomes = if emails.is_empty() {
+        Vec::new()
+    } else {
+        email_client.send_batch(&emails).instrument(send_span).await
+    };
+
+    if outcomes.len() != tasks.len() {
+        tracing::error!(
+            n_emails = tasks.len(),
+            n_outcomes = outcomes.len(),
+            "The email client did not return one outcome per email.",
+        );
+    }
+    // An email without an outcome may not have been sent: try it again.
+    let mut outcomes = outcomes.into_iter();
+    for task in &tasks {
+        let outcome = outcomes.next().unwrap_or_else(|| {
+            Err(anyhow::anyhow!("No outcome was returned for the email").into())
+        });
+        record_outcome(&mut transaction, task, outcome).await?;
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Update the email's `email_messages` row and take it off the queue, or
+/// schedule another attempt.
+async fn record_outcome(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    outcome: Result<SendReceipt, EmailError>,
+) -> Result<(), anyhow::Error> {
+    let status = match outcome {
+        Ok(receipt) => {
+            let status = EmailMessageStatus::Sent;
+            let message_id = receipt.message_id.as_deref();
+            update_message(transaction, task.id, status, message_id).await?;
+            return delete_task(transaction, task.id).await;
+        }
+        Err(EmailError::Suppressed) => {
+            tracing::info!(
+                task_id = %task.id,
+                "Dropping a queued email. The recipient is suppressed."
+            );
+            EmailMessageStatus::Suppressed
+        }
+        // Sending it again would get the same answer.
+        Err(error @ EmailError::Rejected { .. }) => {
+            tracing::error!(
+                task_id = %task.id,
+                error.message = %error,
+                "Dropping a queued email. The provider rejected it.",
+            );
+            EmailMessageStatus::Failed
+        }
+        Err(error) if task.n_retries + 1 >= MAX_DELIVERY_ATTEMPTS => {
+            tracing::error!(
+                task_id = %task.id,
+                error.cause_chain = ?error,
+                error.message = %error,
+                n_retries = task.n_retries,
+                "Giving up on a queued email after too many failed attempts.",
+            );
+            EmailMessageStatus::Failed
+        }
+        Err(error) => {
+            let delay = retry_delay(task.n_retries);
+            tracing::warn!(
+                task_id = %task.id,
+                error.cause_chain = ?error,
+                error.message = %error,
+                n_retries = task.n_retries,
+                retry_in_secs = delay.as_secs(),
+                "Failed to deliver a queued email. Retrying later.",
+            );
+            return schedule_retry(transaction, task.id, delay).await;
+        }
+    };
+    update_message(transaction, task.id, status, None).await?;
+    delete_task(transaction, task.id).await
+}
+
+/// Exponential backoff: 30s, 1m, 2m, ... capped at an hour.
+fn retry_delay(n_retries: i32) -> Duration {
+    let exponent = n_retries.clamp(0, 16) as u32;
+    BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(exponent))
+        .min(MAX_RETRY_DELAY)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    id: Uuid,
+    recipient_email: String,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    n_retries: i32,
+    traceparent: Option<String>,
+    headers: Json<Vec<EmailHeader>>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    transaction: &mut PgTransaction,
+) -> Result<Vec<DeliveryTask>, anyhow::Error> {
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT id, recipient_email, subject, html_body, text_body, n_retries,
+            traceparent, headers AS "headers: Json<Vec<EmailHeader>>"
+        FROM delivery_queue
+        WHERE execute_after <= now()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        BATCH_SIZE,
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(tasks)
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM delivery_queue WHERE id = $1"#, task_id)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
+
+/// Record the outcome of a delivery on the email's `email_messages` row.
+#[tracing::instrument(skip(transaction))]
+async fn update_message(
+    transaction: &mut PgTransaction,
+    message_id: Uuid,
+    status: EmailMessageStatus,
+    provider_message_id: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_messages
+        SET status = $2,
+            provider_message_id = COALESCE($3, provider_message_id),
+            updated_at = now()
+        WHERE id = $1
+        "#,
+        message_id,
+        status.as_str(),
+        provider_message_id,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn schedule_retry(
+    transaction: &mut PgTransaction,
+    task_id: Uuid,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE delivery_queue
+        SET n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $2)
+        WHERE id = $1
+        "#,
+        task_id,
+        delay.as_secs_f64(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::retry_delay;
+
+    #[test]
+    fn retry_delay_doubles_with_each_attempt() {
+        assert_eq!(retry_delay(0), Duration::from_secs(30));
+        assert_eq!(retry_delay(1), Duration::from_secs(60));
+        assert_eq!(retry_delay(2), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn retry_delay_is_capped_at_an_hour() {
+        assert_eq!(retry_delay(10), Duration::from_secs(60 * 60));
+        assert_eq!(retry_delay(i32::MAX), Duration::from_secs(60 * 60));
+    }
+}
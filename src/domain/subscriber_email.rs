@@ -1,31 +1,134 @@
 use validator::validate_email;
 
-#[derive(Debug)]
-pub struct SubscriberEmail(String);
+/// An address as the subscriber typed it (trimmed), which is what we send
+/// to, plus a canonical form used to tell whether two addresses are the same
+/// mailbox.
+#[derive(Debug, Clone)]
+pub struct SubscriberEmail {
+    display: String,
+    canonical: String,
+}
+
+/// How far the canonical form goes beyond the domain, which is always
+/// lowercased and converted to punycode.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default)]
+pub struct EmailNormalization {
+    /// Treat `Ursula@` and `ursula@` as one mailbox. Most providers do, but
+    /// RFC 5321 leaves it to the receiving server. Changing this does not
+    /// re-canonicalize addresses already stored.
+    pub case_insensitive_local_part: bool,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SubscriberEmailError {
+    #[error("The email address is malformed.")]
+    Malformed,
+    #[error("The email address has no domain.")]
+    MissingDomain,
+    #[error("The email domain is not a valid hostname.")]
+    InvalidDomain,
+    #[error("Addresses at this domain are not accepted.")]
+    BlockedDomain,
+}
+
+impl SubscriberEmailError {
+    /// A stable identifier for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberEmailError::Malformed => "malformed",
+            SubscriberEmailError::MissingDomain => "missing_domain",
+            SubscriberEmailError::InvalidDomain => "invalid_domain",
+            SubscriberEmailError::BlockedDomain => "blocked_domain",
+        }
+    }
+}
 
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<Self, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
+    pub fn parse(s: String) -> Result<Self, SubscriberEmailError> {
+        Self::parse_with(s, EmailNormalization::default())
+    }
+
+    pub fn parse_with(
+        s: String,
+        normalization: EmailNormalization,
+    ) -> Result<Self, SubscriberEmailError> {
+        let display = s.trim();
+        let (local_part, domain) = display
+            .rsplit_once('@')
+            .ok_or(SubscriberEmailError::Malformed)?;
+        if local_part.is_empty() {
+            return Err(SubscriberEmailError::Malformed);
+        }
+        if domain.is_empty() {
+            return Err(SubscriberEmailError::MissingDomain);
+        }
+        let domain = if domain.starts_with('[') {
+            domain.to_lowercase()
         } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+            idna::domain_to_ascii(domain)
+                .map_err(|_| SubscriberEmailError::InvalidDomain)?
+        };
+        if !is_valid_domain(&domain) {
+            return Err(SubscriberEmailError::InvalidDomain);
         }
+        let local_part = if normalization.case_insensitive_local_part {
+            local_part.to_lowercase()
+        } else {
+            local_part.to_owned()
+        };
+        let canonical = format!("{}@{}", local_part, domain);
+        if !validate_email(&canonical) {
+            return Err(SubscriberEmailError::Malformed);
+        }
+        Ok(Self {
+            display: display.to_owned(),
+            canonical,
+        })
+    }
+
+    /// The form to store and compare addresses by.
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+
+    /// The domain of the canonical form.
+    pub fn domain(&self) -> &str {
+        let (_, domain) = self.canonical.rsplit_once('@').unwrap();
+        domain
+    }
+}
+
+/// Hostname rules (RFC 1123) for an ASCII domain: dot-separated labels of
+/// at most 63 letters, digits or hyphens, not starting or ending with a
+/// hyphen. Address literals such as `[127.0.0.1]` are left to
+/// `validate_email`.
+fn is_valid_domain(domain: &str) -> bool {
+    if domain.starts_with('[') {
+        return true;
     }
+    domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.display
     }
 }
 
 #[cfg(test)]
 mod test {
-    use claims::assert_err;
+    use claims::{assert_err, assert_err_eq};
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
 
-    use super::SubscriberEmail;
+    use super::{EmailNormalization, SubscriberEmail, SubscriberEmailError};
 
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
@@ -52,7 +155,33 @@ mod test {
     #[test]
     fn email_missing_username_part_is_rejected() {
         let email = "@domain.com".to_string();
-        assert_err!(SubscriberEmail::parse(email));
+        assert_err_eq!(
+            SubscriberEmail::parse(email),
+            SubscriberEmailError::Malformed
+        );
+    }
+
+    #[test]
+    fn email_missing_domain_is_rejected() {
+        let email = "ursula@".to_string();
+        assert_err_eq!(
+            SubscriberEmail::parse(email),
+            SubscriberEmailError::MissingDomain
+        );
+    }
+
+    #[test]
+    fn email_with_an_invalid_domain_is_rejected() {
+        for email in [
+            "ursula@-domain.com",
+            "ursula@domain..com",
+            "ursula@do_main.com",
+        ] {
+            assert_err_eq!(
+                SubscriberEmail::parse(email.to_string()),
+                SubscriberEmailError::InvalidDomain
+            );
+        }
     }
 
     #[quickcheck_macros::quickcheck]
@@ -61,4 +190,41 @@ mod test {
     ) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
     }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email =
+            SubscriberEmail::parse("  ursula@example.com \n".into()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@example.com");
+        assert_eq!(email.canonical(), "ursula@example.com");
+    }
+
+    #[test]
+    fn the_domain_is_lowercased_in_the_canonical_form_only() {
+        let email =
+            SubscriberEmail::parse("Ursula@Example.COM".into()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@Example.COM");
+        assert_eq!(email.canonical(), "Ursula@example.com");
+    }
+
+    #[test]
+    fn idn_domains_are_converted_to_punycode() {
+        let email =
+            SubscriberEmail::parse("ursula@Bücher.example".into()).unwrap();
+        assert_eq!(email.as_ref(), "ursula@Bücher.example");
+        assert_eq!(email.canonical(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn the_local_part_is_lowercased_only_when_configured() {
+        let normalization = EmailNormalization {
+            case_insensitive_local_part: true,
+        };
+        let email = SubscriberEmail::parse_with(
+            "Ursula@Example.com".into(),
+            normalization,
+        )
+        .unwrap();
+        assert_eq!(email.canonical(), "ursula@example.com");
+    }
 }
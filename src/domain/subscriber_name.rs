@@ -1,20 +1,49 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+const MAX_LENGTH: usize = 256;
+const FORBIDDEN_CHARS: [char; 9] =
+    ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SubscriberNameError {
+    #[error("The name is empty.")]
+    Empty,
+    #[error(
+        "The name is {length} characters long, the limit is {}.",
+        MAX_LENGTH
+    )]
+    TooLong { length: usize },
+    #[error("The name contains the forbidden character {0:?}.")]
+    ForbiddenCharacter(char),
+}
+
+impl SubscriberNameError {
+    /// A stable identifier for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubscriberNameError::Empty => "empty",
+            SubscriberNameError::TooLong { .. } => "too_long",
+            SubscriberNameError::ForbiddenCharacter(_) => "forbidden_character",
+        }
+    }
+}
+
 impl SubscriberName {
-    pub fn parse(s: String) -> Result<Self, String> {
-        let forbidden_chars = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let is_empty_or_whitespace = s.trim().is_empty();
-        let is_too_long = s.graphemes(true).count() > 256;
-        let contains_forbidden_chars =
-            s.chars().any(|c| forbidden_chars.contains(&c));
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_chars {
-            Err(format!("{} is not a valid subscriber name.", s))
-        } else {
-            Ok(Self(s))
+    pub fn parse(s: String) -> Result<Self, SubscriberNameError> {
+        if s.trim().is_empty() {
+            return Err(SubscriberNameError::Empty);
+        }
+        let length = s.graphemes(true).count();
+        if length > MAX_LENGTH {
+            return Err(SubscriberNameError::TooLong { length });
+        }
+        if let Some(c) = s.chars().find(|c| FORBIDDEN_CHARS.contains(c)) {
+            return Err(SubscriberNameError::ForbiddenCharacter(c));
         }
+        Ok(Self(s))
     }
 
     pub fn inner(self) -> String {
@@ -34,8 +63,8 @@ impl AsRef<str> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::SubscriberName;
-    use claims::{assert_err, assert_ok};
+    use crate::domain::{SubscriberName, SubscriberNameError};
+    use claims::{assert_err, assert_err_eq, assert_ok};
 
     #[test]
     fn a_256_grapheme_long_name_is_valid() {
@@ -46,7 +75,19 @@ mod tests {
     #[test]
     fn a_name_longer_than_256_graphemes_is_rejected() {
         let name = "a".repeat(257);
-        assert_err!(SubscriberName::parse(name));
+        assert_err_eq!(
+            SubscriberName::parse(name),
+            SubscriberNameError::TooLong { length: 257 }
+        );
+    }
+
+    #[test]
+    fn the_too_long_message_states_the_limit() {
+        let error = SubscriberNameError::TooLong { length: 300 };
+        assert_eq!(
+            error.to_string(),
+            "The name is 300 characters long, the limit is 256."
+        );
     }
 
     #[test]
@@ -58,7 +99,7 @@ mod tests {
     #[test]
     fn empty_string_is_invalid() {
         let name = "".to_string();
-        assert_err!(SubscriberName::parse(name));
+        assert_err_eq!(SubscriberName::parse(name), SubscriberNameError::Empty);
     }
 
     #[test]
@@ -68,6 +109,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn the_forbidden_character_is_reported() {
+        let name = "Ursula <Le Guin>".to_string();
+        assert_err_eq!(
+            SubscriberName::parse(name),
+            SubscriberNameError::ForbiddenCharacter('<')
+        );
+    }
+
     #[test]
     fn a_valid_name_is_parsed_successfully() {
         let name = "Ursula Le Guin".to_string();
@@ -0,0 +1,145 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriberStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    /// The address hard-bounced; see `suppressions`.
+    Bounced,
+    /// The subscriber reported our email as spam; see `suppressions`.
+    Complained,
+}
+
+impl SubscriberStatus {
+    pub const ALL: [SubscriberStatus; 5] = [
+        SubscriberStatus::PendingConfirmation,
+        SubscriberStatus::Confirmed,
+        SubscriberStatus::Unsubscribed,
+        SubscriberStatus::Bounced,
+        SubscriberStatus::Complained,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriberStatus::PendingConfirmation => "pending_confirmation",
+            SubscriberStatus::Confirmed => "confirmed",
+            SubscriberStatus::Unsubscribed => "unsubscribed",
+            SubscriberStatus::Bounced => "bounced",
+            SubscriberStatus::Complained => "complained",
+        }
+    }
+
+    /// The single source of truth for the subscriber lifecycle.
+    pub fn can_transition_to(&self, next: SubscriberStatus) -> bool {
+        use SubscriberStatus::*;
+        matches!(
+            (self, next),
+            (PendingConfirmation, Confirmed)
+                | (PendingConfirmation, Unsubscribed)
+                | (Confirmed, Unsubscribed)
+                | (Unsubscribed, PendingConfirmation)
+                | (PendingConfirmation | Confirmed | Unsubscribed, Bounced)
+                | (
+                    PendingConfirmation | Confirmed | Unsubscribed | Bounced,
+                    Complained
+                )
+                | (Bounced | Complained, PendingConfirmation)
+        )
+    }
+
+    /// Every status a subscriber may be in to move to `next`.
+    pub fn predecessors_of(next: SubscriberStatus) -> Vec<SubscriberStatus> {
+        Self::ALL
+            .into_iter()
+            .filter(|s| s.can_transition_to(next))
+            .collect()
+    }
+}
+
+impl TryFrom<String> for SubscriberStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|s| s.as_str() == value)
+            .ok_or_else(|| {
+                format!("'{}' is not a valid subscriber status.", value)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use crate::domain::SubscriberStatus;
+
+    #[test]
+    fn statuses_round_trip_through_their_string_form() {
+        for status in SubscriberStatus::ALL {
+            assert_ok_eq!(
+                SubscriberStatus::try_from(status.as_str().to_string()),
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_statuses_are_rejected() {
+        assert_err!(SubscriberStatus::try_from("deleted".to_string()));
+    }
+
+    #[test]
+    fn a_pending_subscriber_can_confirm_or_unsubscribe() {
+        let status = SubscriberStatus::PendingConfirmation;
+        assert!(status.can_transition_to(SubscriberStatus::Confirmed));
+        assert!(status.can_transition_to(SubscriberStatus::Unsubscribed));
+    }
+
+    #[test]
+    fn an_unsubscribed_subscriber_cannot_be_confirmed_directly() {
+        let status = SubscriberStatus::Unsubscribed;
+        assert!(!status.can_transition_to(SubscriberStatus::Confirmed));
+        assert!(status.can_transition_to(SubscriberStatus::PendingConfirmation));
+    }
+
+    #[test]
+    fn a_confirmed_subscriber_cannot_go_back_to_pending() {
+        let status = SubscriberStatus::Confirmed;
+        assert!(
+            !status.can_transition_to(SubscriberStatus::PendingConfirmation)
+        );
+        assert!(!status.can_transition_to(SubscriberStatus::Confirmed));
+    }
+
+    #[test]
+    fn a_complaint_overrides_a_bounce_but_not_the_other_way_round() {
+        let bounced = SubscriberStatus::Bounced;
+        assert!(bounced.can_transition_to(SubscriberStatus::Complained));
+        let complained = SubscriberStatus::Complained;
+        assert!(!complained.can_transition_to(SubscriberStatus::Bounced));
+    }
+
+    #[test]
+    fn a_suppressed_subscriber_can_only_start_over_as_pending() {
+        for status in [SubscriberStatus::Bounced, SubscriberStatus::Complained]
+        {
+            assert!(
+                status.can_transition_to(SubscriberStatus::PendingConfirmation)
+            );
+            assert!(!status.can_transition_to(SubscriberStatus::Confirmed));
+            assert!(!status.can_transition_to(SubscriberStatus::Unsubscribed));
+        }
+    }
+
+    #[test]
+    fn predecessors_of_unsubscribed_are_pending_and_confirmed() {
+        assert_eq!(
+            SubscriberStatus::predecessors_of(SubscriberStatus::Unsubscribed),
+            vec![
+                SubscriberStatus::PendingConfirmation,
+                SubscriberStatus::Confirmed
+            ]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::domain::{SubscriberEmail, SubscriberEmailError};
+
+/// Disposable-email domains shipped with the application.
+const BUNDLED_BLOCKLIST: &str = include_str!("../config/blocked_domains.txt");
+
+/// Which email domains may subscribe.
+///
+/// A domain is refused if it, or any parent domain, is on the blocklist
+/// and neither it nor a parent is on the allowlist. Both lists combine the
+/// bundled file, `DomainFilterSettings` and the `email_domain_rules` table;
+/// the table is re-read by `reload`, so admin edits apply without a restart.
+#[derive(Clone)]
+pub struct DomainFilter {
+    static_blocked: Arc<HashSet<String>>,
+    static_allowed: Arc<HashSet<String>>,
+    rules: Arc<RwLock<DomainRules>>,
+}
+
+#[derive(Default)]
+struct DomainRules {
+    blocked: HashSet<String>,
+    allowed: HashSet<String>,
+}
+
+impl DomainFilter {
+    pub fn new(blocked: &[String], allowed: &[String]) -> Self {
+        let static_blocked = parse_domain_list(BUNDLED_BLOCKLIST)
+            .chain(blocked.iter().map(|d| normalize(d)))
+            .collect();
+        let static_allowed = allowed.iter().map(|d| normalize(d)).collect();
+        Self {
+            static_blocked: Arc::new(static_blocked),
+            static_allowed: Arc::new(static_allowed),
+            rules: Arc::default(),
+        }
+    }
+
+    pub fn check(
+        &self,
+        email: &SubscriberEmail,
+    ) -> Result<(), SubscriberEmailError> {
+        if self.is_blocked(email.domain()) {
+            Err(SubscriberEmailError::BlockedDomain)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `domain` is expected in canonical form: lowercase, punycode.
+    pub fn is_blocked(&self, domain: &str) -> bool {
+        let rules = self.rules.read().unwrap();
+        let mut blocked = false;
+        for candidate in self_and_parents(domain) {
+            if self.static_allowed.contains(candidate)
+                || rules.allowed.contains(candidate)
+            {
+                return false;
+            }
+            blocked |= self.static_blocked.contains(candidate)
+                || rules.blocked.contains(candidate);
+        }
+        blocked
+    }
+
+    /// Re-read the admin-managed rules.
+    #[tracing::instrument(name = "Reload email domain rules", skip_all)]
+    pub async fn reload(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let rows =
+            sqlx::query!(r#"SELECT domain, rule FROM email_domain_rules"#)
+                .fetch_all(pool)
+                .await?;
+        let mut rules = DomainRules::default();
+        for row in rows {
+            match row.rule.as_str() {
+                "allow" => rules.allowed.insert(row.domain),
+                _ => rules.blocked.insert(row.domain),
+            };
+        }
+        *self.rules.write().unwrap() = rules;
+        Ok(())
+    }
+}
+
+/// Periodically reloads a `DomainFilter`, picking up rules written through
+/// other instances.
+pub struct DomainFilterReloader {
+    filter: DomainFilter,
+    db_pool: PgPool,
+    interval: Duration,
+}
+
+impl DomainFilterReloader {
+    pub fn new(
+        filter: DomainFilter,
+        db_pool: PgPool,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            filter,
+            db_pool,
+            interval,
+        }
+    }
+
+    pub async fn run_until_stopped(self) {
+        loop {
+            if let Err(e) = self.filter.reload(&self.db_pool).await {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Failed to reload email domain rules",
+                );
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+/// Validate a bare domain and bring it to the canonical form addresses use.
+pub fn parse_domain(domain: &str) -> Result<String, SubscriberEmailError> {
+    // `postmaster` exists at every domain (RFC 5321), so this only fails on
+    // the domain itself.
+    let email = format!("postmaster@{}", domain.trim().trim_end_matches('.'));
+    Ok(SubscriberEmail::parse(email)?.domain().to_owned())
+}
+
+/// Like `parse_domain`, for lists we ship or configure ourselves.
+fn normalize(domain: &str) -> String {
+    parse_domain(domain).unwrap_or_else(|_| domain.trim().to_lowercase())
+}
+
+fn parse_domain_list(contents: &str) -> impl Iterator<Item = String> + '_ {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(normalize)
+}
+
+/// `a.b.example.com`, `b.example.com`, `example.com`, `com`.
+fn self_and_parents(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(domain), |d| d.split_once('.').map(|(_, p)| p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DomainFilter;
+
+    fn filter(blocked: &[&str], allowed: &[&str]) -> DomainFilter {
+        let owned =
+            |l: &[&str]| l.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+        DomainFilter::new(&owned(blocked), &owned(allowed))
+    }
+
+    #[test]
+    fn bundled_domains_are_blocked() {
+        assert!(filter(&[], &[]).is_blocked("mailinator.com"));
+    }
+
+    #[test]
+    fn subdomains_of_a_blocked_domain_are_blocked() {
+        assert!(filter(&[], &[]).is_blocked("eu.mailinator.com"));
+    }
+
+    #[test]
+    fn other_domains_are_not_blocked() {
+        let filter = filter(&["example.org"], &[]);
+        assert!(!filter.is_blocked("example.com"));
+        assert!(!filter.is_blocked("notexample.org"));
+    }
+
+    #[test]
+    fn configured_domains_are_normalized() {
+        let filter = filter(&[" Bücher.Example. "], &[]);
+        assert!(filter.is_blocked("xn--bcher-kva.example"));
+    }
+
+    #[test]
+    fn the_allowlist_overrides_the_blocklist() {
+        let filter = filter(&[], &["mailinator.com"]);
+        assert!(!filter.is_blocked("mailinator.com"));
+        assert!(!filter.is_blocked("eu.mailinator.com"));
+    }
+
+    #[test]
+    fn rules_loaded_at_runtime_apply() {
+        let filter = filter(&[], &[]);
+        filter
+            .rules
+            .write()
+            .unwrap()
+            .blocked
+            .insert("example.org".into());
+        assert!(filter.is_blocked("example.org"));
+    }
+}
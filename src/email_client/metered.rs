@@ -0,0 +1,60 @@
+use crate::email_client::{
+    EmailClient, EmailError, EmailSender, OutgoingEmail, SendReceipt,
+};
+use crate::metrics::Metrics;
+
+/// Wraps a provider client and counts the outcome of every send.
+pub struct MeteredEmailClient {
+    inner: EmailClient,
+    provider: &'static str,
+    metrics: Metrics,
+}
+
+impl MeteredEmailClient {
+    pub fn new(
+        inner: EmailClient,
+        provider: &'static str,
+        metrics: Metrics,
+    ) -> Self {
+        Self {
+            inner,
+            provider,
+            metrics,
+        }
+    }
+
+    fn record(&self, outcome: &Result<SendReceipt, EmailError>) {
+        let status_class = match outcome {
+            Ok(_) => "2xx",
+            Err(e) => e.status_class(),
+        };
+        self.metrics.record_email_send(self.provider, status_class);
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for MeteredEmailClient {
+    async fn send(
+        &self,
+        email: &OutgoingEmail,
+    ) -> Result<SendReceipt, EmailError> {
+        let outcome = self.inner.send(email).await;
+        self.record(&outcome);
+        outcome
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Vec<Result<SendReceipt, EmailError>> {
+        let outcomes = self.inner.send_batch(emails).await;
+        for outcome in &outcomes {
+            self.record(outcome);
+        }
+        outcomes
+    }
+
+    async fn check_health(&self) -> Result<(), EmailError> {
+        self.inner.check_health().await
+    }
+}
@@ -0,0 +1,198 @@
+mod metered;
+mod outbox;
+mod postmark;
+mod retry;
+mod smtp;
+mod suppressing;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+pub use metered::MeteredEmailClient;
+pub use outbox::{OutboxEmailClient, OutboxMessage};
+pub use postmark::PostmarkEmailClient;
+pub use retry::RetryPolicy;
+pub use smtp::SmtpEmailClient;
+pub use suppressing::SuppressingEmailClient;
+
+use crate::domain::SubscriberEmail;
+
+/// What the provider told us about an email it accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SendReceipt {
+    /// The provider's id for the message, which its later events about it
+    /// (delivery, bounce, ...) refer to. `None` if the provider accepted
+    /// the email without saying which id it gave it.
+    pub message_id: Option<String>,
+}
+
+/// A header added to an email on top of those the provider sets itself.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Everything needed to send one email.
+#[derive(Debug, Clone)]
+pub struct OutgoingEmail {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub headers: Vec<EmailHeader>,
+    /// Kept by the provider alongside the message and echoed back in its
+    /// events about it. Providers without such a feature drop it.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl OutgoingEmail {
+    pub fn new(
+        recipient: SubscriberEmail,
+        subject: impl Into<String>,
+        html_content: impl Into<String>,
+        text_content: impl Into<String>,
+    ) -> Self {
+        Self {
+            recipient,
+            subject: subject.into(),
+            html_content: html_content.into(),
+            text_content: text_content.into(),
+            headers: Vec::new(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_headers(mut self, headers: Vec<EmailHeader>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn with_metadata(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// A way of getting an email to a recipient. Implemented once per provider;
+/// the rest of the application only talks to this trait.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(
+        &self,
+        email: &OutgoingEmail,
+    ) -> Result<SendReceipt, EmailError>;
+
+    /// Send an email without extra headers or metadata.
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<SendReceipt, EmailError> {
+        let email = OutgoingEmail::new(
+            recipient.clone(),
+            subject,
+            html_content,
+            text_content,
+        );
+        self.send(&email).await
+    }
+
+    /// Send every email in `emails`, returning one outcome per email in the
+    /// same order. A failure only affects the emails it concerns.
+    ///
+    /// Providers with a batch API override this; by default the emails are
+    /// sent one at a time.
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Vec<Result<SendReceipt, EmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+
+    /// A cheap check that the provider can be reached, used by readiness.
+    async fn check_health(&self) -> Result<(), EmailError> {
+        Ok(())
+    }
+}
+
+/// The provider selected in `EmailClientSettings`.
+pub type EmailClient = Arc<dyn EmailSender>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    /// The recipient is on the suppression list, so the provider was never
+    /// called. Retrying will not help.
+    #[error("The recipient is on the suppression list.")]
+    Suppressed,
+    /// The provider accepted the request but refused this email, e.g.
+    /// because the recipient is marked inactive.
+    #[error("The provider rejected the email: {message} (error code {code})")]
+    Rejected { code: i64, message: String },
+    /// The request carrying this email, along with others, failed as a
+    /// whole.
+    #[error("The batch containing the email could not be sent: {message}")]
+    BatchFailed {
+        status_class: &'static str,
+        message: String,
+    },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl EmailError {
+    /// A coarse label for the failure: the provider's status class when it
+    /// answered, `timeout` or `connection` when it did not.
+    pub fn status_class(&self) -> &'static str {
+        let e = match self {
+            EmailError::Suppressed => return "suppressed",
+            EmailError::Rejected { .. } => return "4xx",
+            EmailError::BatchFailed { status_class, .. } => {
+                return status_class
+            }
+            EmailError::UnexpectedError(e) => e,
+        };
+        for cause in e.chain() {
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if e.is_timeout() {
+                    return "timeout";
+                }
+                if let Some(status) = e.status() {
+                    return if status.is_server_error() {
+                        "5xx"
+                    } else {
+                        "4xx"
+                    };
+                }
+                if e.is_connect() {
+                    return "connection";
+                }
+            }
+            if let Some(e) =
+                cause.downcast_ref::<lettre::transport::smtp::Error>()
+            {
+                if e.is_timeout() {
+                    return "timeout";
+                }
+                if e.is_permanent() {
+                    return "5xx";
+                }
+                if e.is_transient() {
+                    return "4xx";
+                }
+                return "connection";
+            }
+        }
+        "error"
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailError, EmailHeader, EmailSender, OutgoingEmail, SendReceipt,
+};
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct OutboxMessage {
+    pub message_id: String,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub headers: Vec<EmailHeader>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Keeps outgoing emails instead of sending them, for local development.
+/// Messages are held in memory and, if a directory is configured, also
+/// written there as one JSON file per email.
+#[derive(Clone)]
+pub struct OutboxEmailClient {
+    sender: SubscriberEmail,
+    dir: Option<PathBuf>,
+    messages: Arc<Mutex<Vec<OutboxMessage>>>,
+}
+
+impl OutboxEmailClient {
+    pub fn new(sender: SubscriberEmail, dir: Option<PathBuf>) -> Self {
+        Self {
+            sender,
+            dir,
+            messages: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn messages(&self) -> Vec<OutboxMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for OutboxEmailClient {
+    async fn send(
+        &self,
+        email: &OutgoingEmail,
+    ) -> Result<SendReceipt, EmailError> {
+        let message_id = Uuid::new_v4();
+        let message = OutboxMessage {
+            message_id: message_id.to_string(),
+            from: self.sender.as_ref().to_owned(),
+            to: email.recipient.as_ref().to_owned(),
+            subject: email.subject.clone(),
+            html_body: email.html_content.clone(),
+            text_body: email.text_content.clone(),
+            headers: email.headers.clone(),
+            metadata: email.metadata.clone(),
+        };
+        if let Some(dir) = &self.dir {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .context("Failed to create the outbox directory")?;
+            let file_name = format!(
+                "{}-{}.json",
+                Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+                message_id
+            );
+            let contents = serde_json::to_vec_pretty(&message)
+                .context("Failed to serialize the outbox message")?;
+            tokio::fs::write(dir.join(file_name), contents)
+                .await
+                .context("Failed to write the outbox message")?;
+        }
+        tracing::info!(
+            recipient = %message.to,
+            subject = %message.subject,
+            "Email kept in the local outbox",
+        );
+        self.messages.lock().unwrap().push(message);
+        Ok(SendReceipt {
+            message_id: Some(message_id.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, OutboxEmailClient, OutgoingEmail};
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_keeps_the_message_in_memory() {
+        let email_client = OutboxEmailClient::new(email(), None);
+        let recipient = email();
+
+        let outcome = email_client
+            .send_email(&recipient, "Subject", "<p>html</p>", "text")
+            .await;
+
+        assert_ok!(outcome);
+        let messages = email_client.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].to, recipient.as_ref());
+        assert_eq!(messages[0].subject, "Subject");
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_a_file_per_message_when_a_dir_is_set() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = OutboxEmailClient::new(email(), Some(dir.clone()));
+
+        email_client
+            .send_email(&email(), "First", "<p>html</p>", "text")
+            .await
+            .unwrap();
+        email_client
+            .send_email(&email(), "Second", "<p>html</p>", "text")
+            .await
+            .unwrap();
+
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_each_email_in_order() {
+        let email_client = OutboxEmailClient::new(email(), None);
+        let emails: Vec<_> = ["First", "Second"]
+            .into_iter()
+            .map(|subject| {
+                OutgoingEmail::new(email(), subject, "<p>html</p>", "text")
+            })
+            .collect();
+
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(Result::is_ok));
+        let subjects: Vec<_> = email_client
+            .messages()
+            .into_iter()
+            .map(|m| m.subject)
+            .collect();
+        assert_eq!(subjects, ["First", "Second"]);
+    }
+}
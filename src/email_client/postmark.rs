@@ -0,0 +1,876 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailError, EmailHeader, EmailSender, OutgoingEmail, RetryPolicy,
+    SendReceipt,
+};
+use crate::telemetry::inject_trace_context;
+
+/// The most messages Postmark accepts in one call to `/email/batch`.
+const BATCH_LIMIT: usize = 500;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    text_body: &'a str,
+    html_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<PostmarkHeader<'a>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+impl<'a> From<&'a EmailHeader> for PostmarkHeader<'a> {
+    fn from(header: &'a EmailHeader) -> Self {
+        Self {
+            name: &header.name,
+            value: &header.value,
+        }
+    }
+}
+
+/// Postmark's verdict on one message; a batch gets one per message, in
+/// request order.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    error_code: i64,
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+/// Sends through Postmark's HTTP API.
+pub struct PostmarkEmailClient {
+    sender: SubscriberEmail,
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret<String>,
+    retry_policy: RetryPolicy,
+    batch_size: usize,
+}
+
+/// Why a single request to Postmark did not go through.
+struct SendFailure {
+    error: anyhow::Error,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+impl PostmarkEmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            authorization_token,
+            base_url,
+            http_client,
+            sender,
+            retry_policy,
+            batch_size: BATCH_LIMIT,
+        }
+    }
+
+    fn request<'a>(&'a self, email: &'a OutgoingEmail) -> SendEmailRequest<'a> {
+        SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: &email.subject,
+            html_body: &email.html_content,
+            text_body: &email.text_content,
+            headers: email.headers.iter().map(PostmarkHeader::from).collect(),
+            metadata: &email.metadata,
+        }
+    }
+
+    /// POST `request_body` to `path`, retrying as the policy allows.
+    async fn post(
+        &self,
+        path: &str,
+        request_body: &(impl serde::Serialize + Sync),
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        let mut attempt = 1;
+        loop {
+            let failure = match self.try_post(path, request_body).await {
+                Ok(response) => return Ok(response),
+                Err(failure) => failure,
+            };
+            if !failure.retryable || attempt >= self.retry_policy.max_attempts {
+                return Err(failure.error);
+            }
+            let delay = match failure.retry_after {
+                // Waiting longer than our cap is left to the delivery worker.
+                Some(delay) if delay > self.retry_policy.max_delay => {
+                    return Err(failure.error);
+                }
+                Some(delay) => delay,
+                None => self.retry_policy.backoff(attempt),
+            };
+            tracing::warn!(
+                error.cause_chain = ?failure.error,
+                attempt,
+                retry_in_ms = delay.as_millis() as u64,
+                "Sending through Postmark failed, retrying."
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn try_post(
+        &self,
+        path: &str,
+        request_body: &(impl serde::Serialize + Sync),
+    ) -> Result<reqwest::Response, SendFailure> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut headers = HeaderMap::new();
+        inject_trace_context(&mut headers);
+        let response = self
+            .http_client
+            .post(url)
+            .headers(headers)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(request_body)
+            .send()
+            .await
+            .map_err(|e| SendFailure {
+                retryable: e.is_timeout() || e.is_connect(),
+                retry_after: None,
+                error: anyhow::Error::new(e)
+                    .context("Failed to reach Postmark"),
+            })?;
+
+        let status = response.status();
+        let retry_after = retry_after(response.headers());
+        let retryable = status.is_server_error()
+            || (status == StatusCode::TOO_MANY_REQUESTS
+                && retry_after.is_some());
+        let Err(e) = response.error_for_status_ref() else {
+            return Ok(response);
+        };
+        let mut error =
+            anyhow::Error::new(e).context("Postmark rejected the email");
+        // Postmark refused the email itself, e.g. an inactive recipient, and
+        // says why. Other 4xx, like a bad server token, are ours to fix.
+        if status == StatusCode::UNPROCESSABLE_ENTITY {
+            if let Ok(body) = response.json::<SendEmailResponse>().await {
+                error = anyhow::Error::new(EmailError::Rejected {
+                    code: body.error_code,
+                    message: body.message,
+                });
+            }
+        }
+        Err(SendFailure {
+            error,
+            retryable,
+            retry_after,
+        })
+    }
+
+    /// Send one chunk of at most `batch_size` emails in a single request.
+    async fn send_chunk(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Vec<Result<SendReceipt, EmailError>> {
+        let request_body: Vec<_> =
+            emails.iter().map(|email| self.request(email)).collect();
+        let results = match self.post("/email/batch", &request_body).await {
+            Ok(response) => response
+                .json::<Vec<SendEmailResponse>>()
+                .await
+                .context("Failed to parse Postmark's batch response"),
+            Err(e) => Err(e),
+        };
+        let results = match results {
+            Ok(results) if results.len() == emails.len() => results,
+            Ok(results) => {
+                let e = anyhow::anyhow!(
+                    "Postmark answered {} results for {} emails",
+                    results.len(),
+                    emails.len()
+                );
+                return batch_failed(e, emails.len());
+            }
+            Err(e) => return batch_failed(e, emails.len()),
+        };
+        results
+            .into_iter()
+            .map(|result| match result.error_code {
+                0 => Ok(SendReceipt {
+                    message_id: result.message_id,
+                }),
+                code => Err(EmailError::Rejected {
+                    code,
+                    message: result.message,
+                }),
+            })
+            .collect()
+    }
+}
+
+/// The same failure for each of the `n` emails of a chunk.
+fn batch_failed(
+    e: anyhow::Error,
+    n: usize,
+) -> Vec<Result<SendReceipt, EmailError>> {
+    let message = format!("{:#}", e);
+    let status_class = email_error(e).status_class();
+    (0..n)
+        .map(|_| {
+            Err(EmailError::BatchFailed {
+                status_class,
+                message: message.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Surfaces a rejection read by `try_post` as such.
+fn email_error(e: anyhow::Error) -> EmailError {
+    match e.downcast::<EmailError>() {
+        Ok(e) => e,
+        Err(e) => e.into(),
+    }
+}
+
+/// Reads a `Retry-After` header given in seconds. The HTTP-date form is not
+/// used by Postmark and is ignored.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkEmailClient {
+    async fn send(
+        &self,
+        email: &OutgoingEmail,
+    ) -> Result<SendReceipt, EmailError> {
+        let request_body = self.request(email);
+        let response = self
+            .post("/email", &request_body)
+            .await
+            .map_err(email_error)?;
+        // The email was accepted whatever the body says: failing here would
+        // have the delivery worker send it again.
+        let message_id = match response.json::<SendEmailResponse>().await {
+            Ok(response) => response.message_id,
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Postmark accepted an email without a readable MessageID.",
+                );
+                None
+            }
+        };
+        Ok(SendReceipt { message_id })
+    }
+
+    /// Sends through `/email/batch`, in chunks of at most 500 emails. A
+    /// single email goes through `/email`.
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Vec<Result<SendReceipt, EmailError>> {
+        if let [email] = emails {
+            // Nothing to batch: `/email` does the same for a single email.
+            return vec![self.send(email).await];
+        }
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(self.batch_size) {
+            outcomes.extend(self.send_chunk(chunk).await);
+        }
+        outcomes
+    }
+
+    async fn check_health(&self) -> Result<(), EmailError> {
+        let url = format!("{}/server", self.base_url);
+        self.http_client
+            .get(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .send()
+            .await
+            .context("Failed to reach Postmark")?
+            .error_for_status()
+            .context("Postmark rejected the health check")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{
+        any, body_partial_json, header, header_exists, method, path,
+    };
+    use wiremock::Request;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::postmark::PostmarkEmailClient;
+    use crate::email_client::{
+        EmailError, EmailHeader, EmailSender, OutgoingEmail, RetryPolicy,
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> =
+                serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(base_url: String) -> PostmarkEmailClient {
+        PostmarkEmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(100),
+            RetryPolicy::none(),
+        )
+    }
+
+    fn outgoing_emails(n: usize) -> Vec<OutgoingEmail> {
+        (0..n)
+            .map(|_| {
+                OutgoingEmail::new(email(), subject(), content(), content())
+            })
+            .collect()
+    }
+
+    /// Answers a batch request with one success per message.
+    struct AcceptAll;
+
+    impl wiremock::Respond for AcceptAll {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: Vec<serde_json::Value> =
+                serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = body
+                .iter()
+                .map(|message| {
+                    serde_json::json!({
+                        "ErrorCode": 0,
+                        "Message": "OK",
+                        "MessageID": uuid::Uuid::new_v4(),
+                        "To": message["To"],
+                    })
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    fn retrying_email_client(base_url: String) -> PostmarkEmailClient {
+        PostmarkEmailClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(100),
+            RetryPolicy {
+                max_attempts: 3,
+                base_delay: std::time::Duration::from_millis(10),
+                max_delay: std::time::Duration::from_millis(100),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_passes_headers_and_metadata_to_postmark() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let email =
+            OutgoingEmail::new(email(), subject(), content(), content())
+                .with_headers(vec![EmailHeader {
+                    name: "List-Unsubscribe-Post".into(),
+                    value: "List-Unsubscribe=One-Click".into(),
+                }])
+                .with_metadata("email_message_id", "42");
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{
+                    "Name": "List-Unsubscribe-Post",
+                    "Value": "List-Unsubscribe=One-Click",
+                }],
+                "Metadata": { "email_message_id": "42" },
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client.send(&email).await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_leaves_out_empty_headers_and_metadata() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+            .unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: serde_json::Value = requests[0].body_json().unwrap();
+        assert!(body.get("Headers").is_none());
+        assert!(body.get("Metadata").is_none());
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_message_id_postmark_assigned() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+                }),
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let receipt = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            receipt.message_id.as_deref(),
+            Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200)
+            .set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_a_500_and_succeeds_on_a_later_attempt() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_max_attempts() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_a_4xx() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_a_429_with_retry_after() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("Retry-After", "0"),
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_a_429_without_retry_after() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_wait_for_a_retry_after_beyond_the_cap() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("Retry-After", "60"),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_after_a_timeout() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_delay(std::time::Duration::from_secs(180)),
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_batch_posts_to_the_batch_endpoint_in_chunks() {
+        let mock_server = MockServer::start().await;
+        let mut email_client = email_client(mock_server.uri());
+        email_client.batch_size = 2;
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(AcceptAll)
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&outgoing_emails(5)).await;
+
+        assert_eq!(outcomes.len(), 5);
+        assert!(outcomes.iter().all(|o| o
+            .as_ref()
+            .unwrap()
+            .message_id
+            .is_some()));
+        let requests = mock_server.received_requests().await.unwrap();
+        let sizes: Vec<_> = requests
+            .iter()
+            .map(|r| r.body_json::<Vec<serde_json::Value>>().unwrap().len())
+            .collect();
+        assert_eq!(sizes, [2, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_rejections_per_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!([
+                    { "ErrorCode": 0, "Message": "OK" },
+                    {
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to an inactive recipient."
+                    },
+                    { "ErrorCode": 0, "Message": "OK" },
+                ]),
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&outgoing_emails(3)).await;
+
+        assert_ok!(&outcomes[0]);
+        assert!(matches!(
+            outcomes[1],
+            Err(EmailError::Rejected { code: 406, .. })
+        ));
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_single_recipient_batch_is_not_retried() {
+        let mock_server = MockServer::start().await;
+        let email_client = retrying_email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(422).set_body_json(
+                serde_json::json!({
+                    "ErrorCode": 406,
+                    "Message": "You tried to send to an inactive recipient."
+                }),
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&outgoing_emails(1)).await;
+
+        assert!(matches!(
+            outcomes[..],
+            [Err(EmailError::Rejected { code: 406, .. })]
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_failed_chunk_only_fails_its_own_recipients() {
+        let mock_server = MockServer::start().await;
+        let mut email_client = email_client(mock_server.uri());
+        email_client.batch_size = 2;
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(AcceptAll)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&outgoing_emails(3)).await;
+
+        for outcome in &outcomes[..2] {
+            let e = outcome.as_ref().unwrap_err();
+            assert!(matches!(e, EmailError::BatchFailed { .. }));
+            assert_eq!(e.status_class(), "5xx");
+        }
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_recipient_on_a_malformed_response() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!([{ "ErrorCode": 0, "Message": "OK" }]),
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcomes = email_client.send_batch(&outgoing_emails(2)).await;
+
+        assert!(outcomes
+            .iter()
+            .all(|o| matches!(o, Err(EmailError::BatchFailed { .. }))));
+    }
+}
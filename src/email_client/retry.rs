@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// How often, and how patiently, a provider client retries a failed send.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// A single attempt, no retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    /// How long to wait after the `attempt`-th failure (1-based): a random
+    /// duration up to `base_delay * 2^(attempt - 1)`, capped at `max_delay`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.ceiling(attempt).as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling))
+    }
+
+    fn ceiling(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn the_ceiling_doubles_with_each_attempt() {
+        let policy = policy();
+        assert_eq!(policy.ceiling(1), Duration::from_millis(100));
+        assert_eq!(policy.ceiling(2), Duration::from_millis(200));
+        assert_eq!(policy.ceiling(3), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn the_ceiling_is_capped() {
+        let policy = policy();
+        assert_eq!(policy.ceiling(5), Duration::from_secs(1));
+        assert_eq!(policy.ceiling(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_never_exceeds_the_ceiling() {
+        let policy = policy();
+        for attempt in 1..10 {
+            for _ in 0..100 {
+                assert!(policy.backoff(attempt) <= policy.ceiling(attempt));
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use anyhow::Context;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailError, EmailSender, OutgoingEmail, SendReceipt,
+};
+
+/// Sends through a generic SMTP relay.
+pub struct SmtpEmailClient {
+    sender: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpEmailClient {
+    pub fn new(
+        host: &str,
+        port: u16,
+        require_tls: bool,
+        credentials: Option<(String, Secret<String>)>,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = if require_tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("Failed to configure STARTTLS for the SMTP relay")?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        let sender = sender
+            .as_ref()
+            .parse()
+            .context("The sender is not a valid mailbox")?;
+        Ok(Self {
+            sender,
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpEmailClient {
+    /// SMTP has nowhere to keep metadata, so it is not sent.
+    async fn send(
+        &self,
+        email: &OutgoingEmail,
+    ) -> Result<SendReceipt, EmailError> {
+        let recipient: Mailbox = email
+            .recipient
+            .as_ref()
+            .parse()
+            .context("The recipient is not a valid mailbox")?;
+        // We pick the Message-ID so that it can be returned as the receipt.
+        let message_id = format!(
+            "<{}@{}>",
+            uuid::Uuid::new_v4(),
+            self.sender.email.domain()
+        );
+        let mut message = Message::builder()
+            .message_id(Some(message_id.clone()))
+            .from(self.sender.clone())
+            .to(recipient)
+            .subject(&email.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                email.text_content.clone(),
+                email.html_content.clone(),
+            ))
+            .context("Failed to build the email message")?;
+        for header in &email.headers {
+            let name = HeaderName::new_from_ascii(header.name.clone())
+                .with_context(|| {
+                    format!("{:?} is not a valid header name", header.name)
+                })?;
+            message
+                .headers_mut()
+                .insert_raw(HeaderValue::new(name, header.value.clone()));
+        }
+        self.transport.send(message).await.map_err(send_error)?;
+        Ok(SendReceipt {
+            message_id: Some(message_id),
+        })
+    }
+
+    async fn check_health(&self) -> Result<(), EmailError> {
+        let connected = self
+            .transport
+            .test_connection()
+            .await
+            .context("Failed to reach the SMTP relay")?;
+        if !connected {
+            return Err(anyhow::anyhow!("The SMTP relay refused a NOOP").into());
+        }
+        Ok(())
+    }
+}
+
+/// A permanent (5xx) reply will not change on retry, so it is reported as
+/// a rejection carrying the SMTP reply code.
+fn send_error(e: lettre::transport::smtp::Error) -> EmailError {
+    if e.is_permanent() {
+        return EmailError::Rejected {
+            code: e
+                .status()
+                .and_then(|code| code.to_string().parse().ok())
+                .unwrap_or_default(),
+            message: e.to_string(),
+        };
+    }
+    anyhow::Error::new(e)
+        .context("The SMTP relay failed to send the email")
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{
+        EmailError, EmailHeader, EmailSender, OutgoingEmail, SmtpEmailClient,
+    };
+
+    /// A bare-bones SMTP server that accepts everything and records each
+    /// message's DATA section. `reject_rcpt` makes it refuse recipients.
+    struct SmtpStandIn {
+        port: u16,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SmtpStandIn {
+        async fn start(reject_rcpt: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let messages = Arc::new(Mutex::new(Vec::new()));
+            let store = messages.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let store = store.clone();
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = stream.into_split();
+                        let mut lines = BufReader::new(reader).lines();
+                        writer.write_all(b"220 localhost ESMTP\r\n").await.ok();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            let command = line.to_uppercase();
+                            let reply: &[u8] = if command.starts_with("EHLO")
+                                || command.starts_with("HELO")
+                            {
+                                b"250 localhost\r\n"
+                            } else if command.starts_with("RCPT") && reject_rcpt
+                            {
+                                b"550 No such user\r\n"
+                            } else if command.starts_with("DATA") {
+                                writer
+                                    .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                                    .await
+                                    .ok();
+                                let mut data = String::new();
+                                while let Ok(Some(line)) =
+                                    lines.next_line().await
+                                {
+                                    if line == "." {
+                                        break;
+                                    }
+                                    data.push_str(&line);
+                                    data.push('\n');
+                                }
+                                store.lock().unwrap().push(data);
+                                b"250 OK\r\n"
+                            } else if command.starts_with("QUIT") {
+                                writer.write_all(b"221 Bye\r\n").await.ok();
+                                break;
+                            } else {
+                                b"250 OK\r\n"
+                            };
+                            writer.write_all(reply).await.ok();
+                        }
+                    });
+                }
+            });
+            Self { port, messages }
+        }
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(port: u16) -> SmtpEmailClient {
+        SmtpEmailClient::new(
+            "127.0.0.1",
+            port,
+            false,
+            None,
+            email(),
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message_to_the_relay() {
+        let server = SmtpStandIn::start(false).await;
+        let email_client = email_client(server.port);
+        let recipient = email();
+
+        let outcome = email_client
+            .send_email(&recipient, "Subject line", "<p>html</p>", "text")
+            .await;
+
+        assert_ok!(outcome);
+        let messages = server.messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains(&format!("To: {}", recipient.as_ref())));
+        assert!(messages[0].contains("Subject: Subject line"));
+        assert!(messages[0].contains("multipart/alternative"));
+        assert!(messages[0].contains("<p>html</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_adds_the_extra_headers_to_the_message() {
+        let server = SmtpStandIn::start(false).await;
+        let email_client = email_client(server.port);
+        let email =
+            OutgoingEmail::new(email(), "Subject line", "<p>html</p>", "text")
+                .with_headers(vec![EmailHeader {
+                    name: "List-Unsubscribe-Post".into(),
+                    value: "List-Unsubscribe=One-Click".into(),
+                }]);
+
+        let outcome = email_client.send(&email).await;
+
+        assert_ok!(outcome);
+        let messages = server.messages.lock().unwrap();
+        assert!(messages[0]
+            .contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_rejects_the_recipient() {
+        let server = SmtpStandIn::start(true).await;
+        let email_client = email_client(server.port);
+
+        let outcome = email_client
+            .send_email(&email(), "Subject line", "<p>html</p>", "text")
+            .await;
+
+        assert_err!(outcome);
+        assert!(server.messages.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_permanent_reply_is_reported_as_a_rejection() {
+        let server = SmtpStandIn::start(true).await;
+        let email_client = email_client(server.port);
+
+        let outcome = email_client
+            .send_email(&email(), "Subject line", "<p>html</p>", "text")
+            .await;
+
+        assert!(matches!(
+            outcome,
+            Err(EmailError::Rejected { code: 550, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_relay_is_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let email_client = email_client(port);
+
+        let outcome = email_client
+            .send_email(&email(), "Subject line", "<p>html</p>", "text")
+            .await;
+
+        assert_err!(outcome);
+    }
+}
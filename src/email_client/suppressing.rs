@@ -0,0 +1,92 @@
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::email_client::{
+    EmailClient, EmailError, EmailSender, OutgoingEmail, SendReceipt,
+};
+use crate::suppressions::suppressed_among;
+
+/// Wraps a client and refuses to send to suppressed addresses, before the
+/// provider is called. Recipients are matched by their canonical form, so
+/// they must be parsed with the configured normalization.
+pub struct SuppressingEmailClient {
+    inner: EmailClient,
+    db_pool: PgPool,
+}
+
+impl SuppressingEmailClient {
+    pub fn new(inner: EmailClient, db_pool: PgPool) -> Self {
+        Self { inner, db_pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SuppressingEmailClient {
+    async fn send(
+        &self,
+        email: &OutgoingEmail,
+    ) -> Result<SendReceipt, EmailError> {
+        let canonical = email.recipient.canonical().to_owned();
+        let suppressed = suppressed_among(&self.db_pool, &[canonical])
+            .await
+            .context("Failed to check the suppression list")?;
+        if !suppressed.is_empty() {
+            return Err(EmailError::Suppressed);
+        }
+        self.inner.send(email).await
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Vec<Result<SendReceipt, EmailError>> {
+        let canonicals: Vec<_> = emails
+            .iter()
+            .map(|e| e.recipient.canonical().to_owned())
+            .collect();
+        let suppressed =
+            match suppressed_among(&self.db_pool, &canonicals).await {
+                Ok(suppressed) => suppressed,
+                Err(e) => {
+                    let e = anyhow::Error::new(e)
+                        .context("Failed to check the suppression list");
+                    let message = format!("{:#}", e);
+                    return emails
+                        .iter()
+                        .map(|_| {
+                            Err(EmailError::BatchFailed {
+                                status_class: "error",
+                                message: message.clone(),
+                            })
+                        })
+                        .collect();
+                }
+            };
+        let allowed: Vec<_> = emails
+            .iter()
+            .zip(&canonicals)
+            .filter(|(_, canonical)| !suppressed.contains(*canonical))
+            .map(|(email, _)| email.clone())
+            .collect();
+        let mut sent = self.inner.send_batch(&allowed).await.into_iter();
+        canonicals
+            .iter()
+            .map(|canonical| {
+                if suppressed.contains(canonical) {
+                    Err(EmailError::Suppressed)
+                } else {
+                    sent.next().unwrap_or_else(|| {
+                        Err(anyhow::anyhow!(
+                            "No outcome was returned for the email"
+                        )
+                        .into())
+                    })
+                }
+            })
+            .collect()
+    }
+
+    async fn check_health(&self) -> Result<(), EmailError> {
+        self.inner.check_health().await
+    }
+}
@@ -0,0 +1,126 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use actix_web_lab::middleware::Next;
+use tracing_actix_web::RequestId;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// The body of every error response.
+///
+/// `code` is stable and meant for programs, `message` is for people, and
+/// `request_id` matches the `request_id` on the request's log lines.
+#[derive(serde::Serialize, Debug)]
+pub struct ErrorEnvelope {
+    code: &'static str,
+    message: String,
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<FieldError>,
+}
+
+/// What is wrong with one field of a request.
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(
+        field: &'static str,
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            field,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl ErrorEnvelope {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            request_id: REQUEST_ID.try_with(|id| id.to_string()).ok(),
+            details: Vec::new(),
+        }
+    }
+
+    /// For failures whose cause is logged but not shown to clients.
+    pub fn internal() -> Self {
+        Self::new("internal_error", "Something went wrong on our side.")
+    }
+
+    pub fn with_details(mut self, details: Vec<FieldError>) -> Self {
+        self.details = details;
+        self
+    }
+
+    pub fn into_response(self, status: StatusCode) -> HttpResponse {
+        HttpResponse::build(status).json(self)
+    }
+}
+
+/// Makes the request ID assigned by `TracingLogger` available to
+/// `ErrorEnvelope::new`. Must be wrapped inside `TracingLogger`.
+///
+/// Errors returned by inner middleware (rate limits, auth guards) are
+/// rendered here, inside the scope: left to actix, they would be rendered
+/// after it ends and carry no request ID.
+pub async fn scope_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().copied();
+    match request_id {
+        Some(request_id) => {
+            REQUEST_ID
+                .scope(request_id, async move {
+                    next.call(req).await.map_err(|e| {
+                        let response = e.error_response();
+                        InternalError::from_response(e, response).into()
+                    })
+                })
+                .await
+        }
+        None => next.call(req).await,
+    }
+}
+
+/// Extractor rejections (malformed forms, queries and JSON bodies) wrapped
+/// in the envelope instead of actix's plain-text default.
+fn extractor_error<E>(error: E, _req: &HttpRequest) -> actix_web::Error
+where
+    E: ResponseError + 'static,
+{
+    let response = ErrorEnvelope::new("invalid_request", error.to_string())
+        .into_response(error.status_code());
+    InternalError::from_response(error, response).into()
+}
+
+pub fn form_config() -> web::FormConfig {
+    web::FormConfig::default().error_handler(extractor_error)
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(extractor_error)
+}
+
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(extractor_error)
+}
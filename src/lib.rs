@@ -1,6 +1,19 @@
+pub mod authentication;
+pub mod canonical_emails;
 pub mod configuration;
+pub mod delivery_worker;
 pub mod domain;
+pub mod domain_filter;
 pub mod email_client;
+pub mod errors;
+pub mod metrics;
+pub mod rate_limit;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod suppressions;
+pub mod sweeper;
 pub mod telemetry;
+pub mod templates;
+pub mod utils;
@@ -1,19 +1,69 @@
-use zero2prod::configuration::get_configuration;
-use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use zero2prod::authentication::{create_user, PASSWORD_LENGTH};
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::startup::{get_connection_pool, Application};
+use zero2prod::telemetry::{get_subscriber, init_subscriber, init_tracer};
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    let config = get_configuration().expect("Failed to read configuration.");
+
     /* Logging start */
-    let subscriber =
-        get_subscriber("Zero2Prod".into(), "info".into(), std::io::stdout);
+    let tracer = init_tracer(&config.telemetry);
+    let subscriber = get_subscriber(
+        "Zero2Prod".into(),
+        "info".into(),
+        std::io::stdout,
+        tracer,
+    );
     init_subscriber(subscriber);
     /* Logging end */
 
-    let config = get_configuration().expect("Failed to read configuration.");
+    let mut args = std::env::args().skip(1);
+    if let Some(command) = args.next() {
+        let outcome = match (command.as_str(), args.next()) {
+            ("create-admin", Some(username)) => {
+                create_admin(&config, &username).await
+            }
+            _ => Err(anyhow::anyhow!(
+                "Usage: zero2prod [create-admin <username>]"
+            )),
+        };
+        opentelemetry::global::shutdown_tracer_provider();
+        return outcome.map_err(|e| std::io::Error::other(format!("{:#}", e)));
+    }
 
     let application = Application::build(config).await?;
 
-    application.run_until_stopped().await?;
+    let outcome = application.run_until_stopped().await;
+    // Flush whatever spans are still batched up.
+    opentelemetry::global::shutdown_tracer_provider();
+    outcome
+}
+
+/// Create a user with the password read from the first line of stdin, so
+/// it stays out of the shell history and the process list.
+async fn create_admin(
+    config: &Settings,
+    username: &str,
+) -> Result<(), anyhow::Error> {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .context("Failed to read the password from stdin")?;
+    let password: Secret<String> =
+        Secret::new(password.trim_end_matches(['\r', '\n']).into());
+    let length = password.expose_secret().chars().count();
+    if !PASSWORD_LENGTH.contains(&length) {
+        anyhow::bail!(
+            "The password must be between {} and {} characters long.",
+            PASSWORD_LENGTH.start(),
+            PASSWORD_LENGTH.end()
+        );
+    }
+    let db_pool = get_connection_pool(&config.database);
+    let user_id = create_user(username, password, &db_pool).await?;
+    println!("Created user {} with id {}", username, user_id);
     Ok(())
 }
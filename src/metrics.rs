@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{web, HttpResponse};
+use actix_web_lab::middleware::Next;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+/// Upper bound on the connection acquired to sample the pool's wait time.
+const ACQUIRE_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Every metric the application exports, registered on its own registry so
+/// that each `Application` reports only what it did.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_size: IntGauge,
+    db_pool_idle: IntGauge,
+    db_pool_acquire_wait: Gauge,
+    email_sends: IntCounterVec,
+    pub subscriptions_created: IntCounter,
+    pub subscriptions_confirmed: IntCounter,
+    pub subscriptions_blocked: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests served."),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent serving HTTP requests.",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let db_pool_size = IntGauge::new(
+            "db_pool_connections",
+            "Connections currently open in the pool.",
+        )?;
+        let db_pool_idle = IntGauge::new(
+            "db_pool_idle_connections",
+            "Open connections not currently in use.",
+        )?;
+        let db_pool_acquire_wait = Gauge::new(
+            "db_pool_acquire_wait_seconds",
+            "Time taken to acquire a connection when last scraped.",
+        )?;
+        let email_sends = IntCounterVec::new(
+            Opts::new("email_sends_total", "Emails handed to a provider."),
+            &["provider", "status_class"],
+        )?;
+        let subscriptions_created = IntCounter::new(
+            "subscriptions_created_total",
+            "New subscribers added to the list.",
+        )?;
+        let subscriptions_confirmed = IntCounter::new(
+            "subscriptions_confirmed_total",
+            "Subscribers who confirmed their address.",
+        )?;
+
+        let subscriptions_blocked = IntCounter::new(
+            "subscriptions_blocked_total",
+            "Subscription attempts refused for the address's domain.",
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(db_pool_size.clone()))?;
+        registry.register(Box::new(db_pool_idle.clone()))?;
+        registry.register(Box::new(db_pool_acquire_wait.clone()))?;
+        registry.register(Box::new(email_sends.clone()))?;
+        registry.register(Box::new(subscriptions_created.clone()))?;
+        registry.register(Box::new(subscriptions_confirmed.clone()))?;
+        registry.register(Box::new(subscriptions_blocked.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_size,
+            db_pool_idle,
+            db_pool_acquire_wait,
+            email_sends,
+            subscriptions_created,
+            subscriptions_confirmed,
+            subscriptions_blocked,
+        })
+    }
+
+    pub fn record_email_send(&self, provider: &str, status_class: &str) {
+        self.email_sends
+            .with_label_values(&[provider, status_class])
+            .inc();
+    }
+
+    async fn sample_pool(&self, pool: &PgPool) {
+        self.db_pool_size.set(pool.size() as i64);
+        self.db_pool_idle.set(pool.num_idle() as i64);
+        let start = Instant::now();
+        // Dropped straight away; only the wait matters.
+        let _ =
+            tokio::time::timeout(ACQUIRE_PROBE_TIMEOUT, pool.acquire()).await;
+        self.db_pool_acquire_wait.set(start.elapsed().as_secs_f64());
+    }
+}
+
+/// Records a count and a latency for every request, keyed by the matched
+/// route pattern rather than the raw path to keep label cardinality bounded.
+/// Requests turned away by an inner middleware (rate limits, auth guards)
+/// arrive here as errors and are counted with the status they turn into.
+pub async fn track_http_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+    let start = Instant::now();
+    let outcome = next.call(req).await;
+    if let Some(metrics) = metrics {
+        let status = match &outcome {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        let status = status.as_u16().to_string();
+        let labels = [method.as_str(), route.as_str(), status.as_str()];
+        metrics.http_requests.with_label_values(&labels).inc();
+        metrics
+            .http_request_duration
+            .with_label_values(&labels)
+            .observe(start.elapsed().as_secs_f64());
+    }
+    outcome
+}
+
+pub async fn metrics_endpoint(
+    metrics: web::Data<Metrics>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    metrics.sample_pool(&db_pool).await;
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut buffer) {
+        tracing::error!(error.message = %e, "Failed to encode metrics");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer)
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use actix_web_lab::middleware::Next;
+use serde_aux::field_attributes::deserialize_number_from_string;
+use sqlx::PgPool;
+
+use crate::configuration::RateLimitSettings;
+use crate::errors::ErrorEnvelope;
+
+/// Expired windows are swept from the in-memory backend once it tracks
+/// this many keys.
+const MEMORY_SWEEP_THRESHOLD: usize = 10_000;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackendKind {
+    Postgres,
+    Memory,
+}
+
+/// At most `max_requests` in each window of `window_secs`.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct Quota {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_secs: u64,
+}
+
+impl Quota {
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs)
+    }
+}
+
+/// The limits enforced by the public routes.
+#[derive(Clone, Copy, Debug)]
+pub enum Limit {
+    SubscribePerIp,
+    SubscribePerEmail,
+    ConfirmPerIp,
+}
+
+impl Limit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Limit::SubscribePerIp => "subscribe_per_ip",
+            Limit::SubscribePerEmail => "subscribe_per_email",
+            Limit::ConfirmPerIp => "confirm_per_ip",
+        }
+    }
+}
+
+/// A request refused because its quota is used up.
+#[derive(thiserror::Error, Debug)]
+#[error("Too many requests, try again later.")]
+pub struct Throttled {
+    pub retry_after: Duration,
+}
+
+impl ResponseError for Throttled {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        // Whole seconds, rounded up so a client retrying on time succeeds.
+        let secs = self.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        let mut response = ErrorEnvelope::new("rate_limited", self.to_string())
+            .into_response(self.status_code());
+        response.headers_mut().insert(RETRY_AFTER, secs.into());
+        response
+    }
+}
+
+/// Counts requests per key in fixed windows, in the backend picked in
+/// configuration.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: RateLimitStore,
+    settings: RateLimitSettings,
+}
+
+#[derive(Clone)]
+enum RateLimitStore {
+    Postgres(PgPool),
+    Memory(Arc<Mutex<HashMap<String, Window>>>),
+}
+
+struct Window {
+    ends_at: Instant,
+    hits: u32,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings, db_pool: PgPool) -> Self {
+        let store = match settings.backend {
+            RateLimitBackendKind::Postgres => RateLimitStore::Postgres(db_pool),
+            RateLimitBackendKind::Memory => {
+                RateLimitStore::Memory(Arc::default())
+            }
+        };
+        Self { store, settings }
+    }
+
+    /// The address quotas are counted against: the one in
+    /// `client_ip_header` if configured and present, otherwise the peer.
+    /// `X-Forwarded-For` is never read, as its leftmost entry is whatever
+    /// the client sent.
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        let forwarded = self
+            .settings
+            .client_ip_header
+            .as_deref()
+            .and_then(|name| req.headers().get(name))
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty());
+        match forwarded {
+            Some(ip) => ip.to_owned(),
+            None => req
+                .peer_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "unknown".into()),
+        }
+    }
+
+    /// Count a request by `subject` against `limit`.
+    ///
+    /// If the backend is unavailable the request is let through: failing
+    /// closed would take the routes down with the store.
+    #[tracing::instrument(name = "Checking rate limit", skip(self, subject))]
+    pub async fn check(
+        &self,
+        limit: Limit,
+        subject: &str,
+    ) -> Result<(), Throttled> {
+        let quota = self.quota(limit);
+        let key = format!("{}:{}", limit.as_str(), subject);
+        let (hits, remaining) = match &self.store {
+            RateLimitStore::Postgres(pool) => {
+                match hit_postgres(pool, &key, quota).await {
+                    Ok(window) => window,
+                    Err(e) => {
+                        tracing::warn!(
+                            error.cause_chain = ?e,
+                            "Failed to record request for rate limiting",
+                        );
+                        return Ok(());
+                    }
+                }
+            }
+            RateLimitStore::Memory(windows) => hit_memory(windows, key, quota),
+        };
+        if hits > quota.max_requests {
+            return Err(Throttled {
+                retry_after: remaining,
+            });
+        }
+        Ok(())
+    }
+
+    fn quota(&self, limit: Limit) -> Quota {
+        match limit {
+            Limit::SubscribePerIp => self.settings.subscribe_per_ip,
+            Limit::SubscribePerEmail => self.settings.subscribe_per_email,
+            Limit::ConfirmPerIp => self.settings.confirm_per_ip,
+        }
+    }
+}
+
+/// Per-IP quota on the routes that send confirmation emails.
+pub async fn limit_subscribe_per_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    limit_per_ip(Limit::SubscribePerIp, req, next).await
+}
+
+/// Per-IP quota on confirmation, so tokens cannot be brute-forced.
+pub async fn limit_confirm_per_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    limit_per_ip(Limit::ConfirmPerIp, req, next).await
+}
+
+async fn limit_per_ip<B: MessageBody>(
+    limit: Limit,
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let rate_limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("RateLimiter is registered as app data")
+        .clone();
+    let ip = rate_limiter.client_ip(req.request());
+    rate_limiter.check(limit, &ip).await?;
+    next.call(req).await
+}
+
+/// Returns the hits in the current window, including this one, and the
+/// time left until the window resets.
+fn hit_memory(
+    windows: &Mutex<HashMap<String, Window>>,
+    key: String,
+    quota: Quota,
+) -> (u32, Duration) {
+    let now = Instant::now();
+    let mut windows = windows.lock().unwrap();
+    if windows.len() >= MEMORY_SWEEP_THRESHOLD {
+        windows.retain(|_, w| w.ends_at > now);
+    }
+    let window = windows.entry(key).or_insert(Window {
+        ends_at: now + quota.window(),
+        hits: 0,
+    });
+    if window.ends_at <= now {
+        window.ends_at = now + quota.window();
+        window.hits = 0;
+    }
+    window.hits = window.hits.saturating_add(1);
+    (window.hits, window.ends_at - now)
+}
+
+/// As `hit_memory`, in a single statement so that concurrent requests on
+/// different instances are counted exactly once each.
+async fn hit_postgres(
+    pool: &PgPool,
+    key: &str,
+    quota: Quota,
+) -> Result<(u32, Duration), sqlx::Error> {
+    let row = sqlx::query!(
+        r#"INSERT INTO rate_limits (key, hits, window_ends_at)
+        VALUES ($1, 1, now() + make_interval(secs => $2))
+        ON CONFLICT (key) DO UPDATE SET
+            hits = CASE WHEN rate_limits.window_ends_at <= now()
+                THEN 1 ELSE rate_limits.hits + 1 END,
+            window_ends_at = CASE WHEN rate_limits.window_ends_at <= now()
+                THEN EXCLUDED.window_ends_at ELSE rate_limits.window_ends_at END
+        RETURNING hits,
+            EXTRACT(EPOCH FROM window_ends_at - now())::float8
+                AS "remaining_secs!""#,
+        key,
+        quota.window().as_secs_f64(),
+    )
+    .fetch_one(pool)
+    .await?;
+    let remaining = Duration::from_secs_f64(row.remaining_secs.max(0.0));
+    Ok((row.hits.max(0) as u32, remaining))
+}
+
+/// Delete the windows that have ended. `hit_postgres` starts a new window
+/// in place of an expired one, but keys that are never hit again would
+/// otherwise stay forever. Returns the number of rows deleted.
+#[tracing::instrument(name = "Deleting expired rate limit windows", skip_all)]
+pub async fn delete_expired_windows(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM rate_limits WHERE window_ends_at <= now()"#
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(max_requests: u32) -> Quota {
+        Quota {
+            max_requests,
+            window_secs: 60,
+        }
+    }
+
+    #[test]
+    fn hits_are_counted_per_key() {
+        let windows = Mutex::default();
+
+        assert_eq!(hit_memory(&windows, "a".into(), quota(2)).0, 1);
+        assert_eq!(hit_memory(&windows, "a".into(), quota(2)).0, 2);
+        assert_eq!(hit_memory(&windows, "b".into(), quota(2)).0, 1);
+    }
+
+    #[test]
+    fn an_expired_window_starts_again() {
+        let windows = Mutex::default();
+        let expired = Quota {
+            max_requests: 1,
+            window_secs: 0,
+        };
+
+        hit_memory(&windows, "a".into(), expired);
+        let (hits, _) = hit_memory(&windows, "a".into(), expired);
+
+        assert_eq!(hits, 1);
+    }
+
+    #[test]
+    fn retry_after_is_rounded_up_to_a_whole_second() {
+        let throttled = Throttled {
+            retry_after: Duration::from_millis(1_200),
+        };
+
+        let response = throttled.error_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "2");
+    }
+}
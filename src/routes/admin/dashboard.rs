@@ -0,0 +1,40 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use minijinja::context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::templates::Templates;
+use crate::utils::e500;
+
+pub async fn admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    db_pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let username = get_username(*user_id, &db_pool).await.map_err(e500)?;
+    let body = templates
+        .render("pages/admin/dashboard.html", context! { username })
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT username FROM users WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
@@ -0,0 +1,162 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::domain_filter::{parse_domain, DomainFilter};
+use crate::errors::{ErrorEnvelope, FieldError};
+use crate::routes::error_chain_fmt;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainRule {
+    Block,
+    Allow,
+}
+
+impl DomainRule {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DomainRule::Block => "block",
+            DomainRule::Allow => "allow",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DomainRuleData {
+    domain: String,
+    rule: DomainRule,
+}
+
+#[derive(serde::Serialize)]
+struct StoredDomainRule {
+    domain: String,
+    rule: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum EmailDomainError {
+    #[error("The domain is invalid.")]
+    ValidationError(FieldError),
+    #[error("There is no rule for this domain.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailDomainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for EmailDomainError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            EmailDomainError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            EmailDomainError::NotFound => StatusCode::NOT_FOUND,
+            EmailDomainError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let envelope = match self {
+            EmailDomainError::ValidationError(detail) => {
+                ErrorEnvelope::new("validation_error", self.to_string())
+                    .with_details(vec![detail.clone()])
+            }
+            EmailDomainError::NotFound => {
+                ErrorEnvelope::new("not_found", self.to_string())
+            }
+            EmailDomainError::UnexpectedError(_) => ErrorEnvelope::internal(),
+        };
+        envelope.into_response(self.status_code())
+    }
+}
+
+#[tracing::instrument(name = "List email domain rules", skip(db_pool))]
+pub async fn list_email_domain_rules(
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, EmailDomainError> {
+    let rules = sqlx::query_as!(
+        StoredDomainRule,
+        r#"SELECT domain, rule, created_at FROM email_domain_rules
+        ORDER BY domain"#
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    .context("Failed to read email domain rules")?;
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+/// Create or replace the rule for a domain. Applies to this instance
+/// straight away, and to the others on their next reload.
+#[tracing::instrument(
+    name = "Save email domain rule",
+    skip(body, db_pool, domain_filter),
+    fields(domain = %body.domain, rule = body.rule.as_str())
+)]
+pub async fn save_email_domain_rule(
+    body: web::Json<DomainRuleData>,
+    db_pool: web::Data<PgPool>,
+    domain_filter: web::Data<DomainFilter>,
+) -> Result<HttpResponse, EmailDomainError> {
+    let domain = parse_domain(&body.domain).map_err(|e| {
+        EmailDomainError::ValidationError(FieldError::new(
+            "domain",
+            e.code(),
+            e.to_string(),
+        ))
+    })?;
+    let rule = sqlx::query_as!(
+        StoredDomainRule,
+        r#"
+        INSERT INTO email_domain_rules (domain, rule) VALUES ($1, $2)
+        ON CONFLICT (domain) DO UPDATE SET rule = EXCLUDED.rule
+        RETURNING domain, rule, created_at
+        "#,
+        domain,
+        body.rule.as_str(),
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    .context("Failed to save the email domain rule")?;
+    domain_filter
+        .reload(&db_pool)
+        .await
+        .context("Failed to reload email domain rules")?;
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+#[tracing::instrument(
+    name = "Delete email domain rule",
+    skip(db_pool, domain_filter)
+)]
+pub async fn delete_email_domain_rule(
+    domain: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    domain_filter: web::Data<DomainFilter>,
+) -> Result<HttpResponse, EmailDomainError> {
+    let domain =
+        parse_domain(&domain).map_err(|_| EmailDomainError::NotFound)?;
+    let result = sqlx::query!(
+        r#"DELETE FROM email_domain_rules WHERE domain = $1"#,
+        domain
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to delete the email domain rule")?;
+    if result.rows_affected() == 0 {
+        return Err(EmailDomainError::NotFound);
+    }
+    domain_filter
+        .reload(&db_pool)
+        .await
+        .context("Failed to reload email domain rules")?;
+    Ok(HttpResponse::NoContent().finish())
+}
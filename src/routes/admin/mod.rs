@@ -0,0 +1,13 @@
+mod dashboard;
+mod email_domains;
+mod logout;
+mod newsletters;
+mod password;
+mod suppressions;
+
+pub use dashboard::admin_dashboard;
+pub use email_domains::*;
+pub use logout::log_out;
+pub use newsletters::*;
+pub use password::*;
+pub use suppressions::*;
@@ -0,0 +1,147 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::delivery_worker::enqueue_email;
+use crate::domain::{EmailNormalization, SubscriberEmail, SubscriberStatus};
+use crate::errors::ErrorEnvelope;
+use crate::routes::{error_chain_fmt, OneClickUnsubscribe};
+use crate::templates::{EmailTemplate, Templates};
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    html: String,
+    /// Generated from `html` when left out.
+    #[serde(default)]
+    text: Option<String>,
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    email: SubscriberEmail,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PublishError::UnexpectedError(_) => ErrorEnvelope::internal(),
+        }
+        .into_response(self.status_code())
+    }
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, db_pool, templates, one_click, normalization),
+    fields(title = %body.title)
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    db_pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+    one_click: web::Data<OneClickUnsubscribe>,
+    normalization: web::Data<EmailNormalization>,
+) -> Result<HttpResponse, PublishError> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to connect to db pool")?;
+    let subscribers =
+        get_confirmed_subscribers(&mut transaction, **normalization).await?;
+    for subscriber in subscribers {
+        match subscriber {
+            Ok(subscriber) => {
+                let unsubscribe_link = one_click.url(subscriber.id);
+                let email = templates.render_email(
+                    EmailTemplate::Newsletter,
+                    minijinja::context! {
+                        title => &body.title,
+                        html => &body.content.html,
+                        text => &body.content.text,
+                        unsubscribe_link,
+                    },
+                )?;
+                enqueue_email(
+                    &mut transaction,
+                    &subscriber.email,
+                    subscriber.id,
+                    EmailTemplate::Newsletter,
+                    &email,
+                    &one_click.headers(subscriber.id),
+                )
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to enqueue newsletter issue for {}",
+                        subscriber.email.as_ref()
+                    )
+                })?;
+            }
+            Err(error) => {
+                tracing::warn!(
+                    error.cause_chain = ?error,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+            }
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit newsletter issue to the delivery queue.")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Get confirmed subscribers", skip(transaction))]
+async fn get_confirmed_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    normalization: EmailNormalization,
+) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT id, email FROM subscriptions
+        WHERE status = $1"#,
+        SubscriberStatus::Confirmed.as_str(),
+    )
+    .fetch_all(transaction)
+    .await?;
+
+    let confirmed_subscribers = rows
+        .into_iter()
+        .map(
+            |r| match SubscriberEmail::parse_with(r.email, normalization) {
+                Ok(email) => Ok(ConfirmedSubscriber { id: r.id, email }),
+                Err(error) => Err(anyhow::Error::new(error)),
+            },
+        )
+        .collect();
+    Ok(confirmed_subscribers)
+}
@@ -0,0 +1,68 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::authentication::{
+    self, validate_credentials, AuthError, Credentials, UserId, PASSWORD_LENGTH,
+};
+use crate::routes::admin::dashboard::get_username;
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+pub async fn change_password(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if form.new_password.expose_secret()
+        != form.new_password_check.expose_secret()
+    {
+        FlashMessage::error(
+            "You entered two different new passwords - \
+            the field values must match.",
+        )
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let password_length = form.new_password.expose_secret().chars().count();
+    if !PASSWORD_LENGTH.contains(&password_length) {
+        FlashMessage::error(format!(
+            "The new password must be between {} and {} characters long.",
+            PASSWORD_LENGTH.start(),
+            PASSWORD_LENGTH.end()
+        ))
+        .send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(*user_id, &db_pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &db_pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.")
+                    .send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    authentication::change_password(*user_id, form.0.new_password, &db_pool)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("Your password has been changed.").send();
+    Ok(see_other("/admin/password"))
+}
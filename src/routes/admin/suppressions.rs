@@ -0,0 +1,139 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::domain::{EmailNormalization, SubscriberEmail};
+use crate::errors::{ErrorEnvelope, FieldError};
+use crate::routes::error_chain_fmt;
+use crate::suppressions::{lift_suppression, SuppressionReason};
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionData {
+    email: String,
+    reason: SuppressionReason,
+}
+
+#[derive(serde::Serialize)]
+struct StoredSuppression {
+    email: String,
+    reason: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+    #[error("The email address is invalid.")]
+    ValidationError(FieldError),
+    #[error("This address is not suppressed.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SuppressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SuppressionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SuppressionError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SuppressionError::NotFound => StatusCode::NOT_FOUND,
+            SuppressionError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let envelope = match self {
+            SuppressionError::ValidationError(detail) => {
+                ErrorEnvelope::new("validation_error", self.to_string())
+                    .with_details(vec![detail.clone()])
+            }
+            SuppressionError::NotFound => {
+                ErrorEnvelope::new("not_found", self.to_string())
+            }
+            SuppressionError::UnexpectedError(_) => ErrorEnvelope::internal(),
+        };
+        envelope.into_response(self.status_code())
+    }
+}
+
+#[tracing::instrument(name = "List suppressed addresses", skip(db_pool))]
+pub async fn list_suppressions(
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+    let suppressions = sqlx::query_as!(
+        StoredSuppression,
+        r#"SELECT email_canonical AS email, reason, created_at
+        FROM suppressions
+        ORDER BY created_at DESC"#
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    .context("Failed to read the suppression list")?;
+    Ok(HttpResponse::Ok().json(suppressions))
+}
+
+/// Suppress an address, or change the reason it is suppressed for.
+#[tracing::instrument(
+    name = "Save suppression",
+    skip(body, db_pool, normalization),
+    fields(reason = body.reason.as_str())
+)]
+pub async fn save_suppression(
+    body: web::Json<SuppressionData>,
+    db_pool: web::Data<PgPool>,
+    normalization: web::Data<EmailNormalization>,
+) -> Result<HttpResponse, SuppressionError> {
+    let email =
+        SubscriberEmail::parse_with(body.email.clone(), **normalization)
+            .map_err(|e| {
+                SuppressionError::ValidationError(FieldError::new(
+                    "email",
+                    e.code(),
+                    e.to_string(),
+                ))
+            })?;
+    let suppression = sqlx::query_as!(
+        StoredSuppression,
+        r#"
+        INSERT INTO suppressions (email_canonical, reason) VALUES ($1, $2)
+        ON CONFLICT (email_canonical) DO UPDATE SET reason = EXCLUDED.reason
+        RETURNING email_canonical AS email, reason, created_at
+        "#,
+        email.canonical(),
+        body.reason.as_str(),
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    .context("Failed to save the suppression")?;
+    Ok(HttpResponse::Ok().json(suppression))
+}
+
+/// Lift a suppression: the address can subscribe and be emailed again.
+#[tracing::instrument(
+    name = "Lift suppression",
+    skip(email, db_pool, normalization)
+)]
+pub async fn delete_suppression(
+    email: web::Path<String>,
+    db_pool: web::Data<PgPool>,
+    normalization: web::Data<EmailNormalization>,
+) -> Result<HttpResponse, SuppressionError> {
+    let email =
+        SubscriberEmail::parse_with(email.into_inner(), **normalization)
+            .map_err(|_| SuppressionError::NotFound)?;
+    let lifted = lift_suppression(db_pool.get_ref(), &email)
+        .await
+        .context("Failed to lift the suppression")?;
+    if !lifted {
+        return Err(SuppressionError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,5 +1,111 @@
-use actix_web::HttpResponse;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
 
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::configuration::ReadinessCheck;
+use crate::email_client::EmailClient;
+
+/// Upper bound on any single dependency probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving requests.
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize)]
+struct DependencyReport {
+    status: Status,
+    required: bool,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessReport {
+    ready: bool,
+    dependencies: BTreeMap<&'static str, DependencyReport>,
+}
+
+/// Readiness: every required dependency answered within `PROBE_TIMEOUT`.
+#[tracing::instrument(name = "Readiness check", skip_all)]
+pub async fn readiness(
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_check: web::Data<ReadinessCheck>,
+) -> HttpResponse {
+    let mut dependencies = BTreeMap::new();
+    let database = probe(true, async {
+        sqlx::query("SELECT 1")
+            .execute(db_pool.get_ref())
+            .await
+            .map(|_| ())
+    });
+    let email_provider = async {
+        match email_check.get_ref() {
+            ReadinessCheck::Skip => None,
+            ReadinessCheck::Optional => {
+                Some(probe(false, email_client.check_health()).await)
+            }
+            ReadinessCheck::Required => {
+                Some(probe(true, email_client.check_health()).await)
+            }
+        }
+    };
+    let (database, email_provider) = tokio::join!(database, email_provider);
+    dependencies.insert("database", database);
+    if let Some(report) = email_provider {
+        dependencies.insert("email_provider", report);
+    }
+
+    let ready = dependencies
+        .values()
+        .all(|d| !d.required || matches!(d.status, Status::Up));
+    let report = ReadinessReport {
+        ready,
+        dependencies,
+    };
+    if ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+async fn probe<E: std::fmt::Display>(
+    required: bool,
+    check: impl Future<Output = Result<(), E>>,
+) -> DependencyReport {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(PROBE_TIMEOUT, check).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    let error = match outcome {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some("Timed out".into()),
+    };
+    if let Some(error) = &error {
+        tracing::warn!(error, required, "Dependency is down");
+    }
+    DependencyReport {
+        status: if error.is_none() {
+            Status::Up
+        } else {
+            Status::Down
+        },
+        required,
+        latency_ms,
+        error,
+    }
+}
@@ -1,15 +1,28 @@
+use actix_web::http::header::ContentType;
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use sqlx::{PgPool, Postgres, Transaction};
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
-use crate::startup::ApplicationBaseUrl;
+use crate::delivery_worker::enqueue_email;
+use crate::domain::{
+    EmailNormalization, NewSubscriber, SubscriberEmail, SubscriberEmailError,
+    SubscriberName, SubscriberStatus,
+};
+use crate::domain_filter::DomainFilter;
+use crate::errors::{ErrorEnvelope, FieldError};
+use crate::metrics::Metrics;
+use crate::rate_limit::{Limit, RateLimiter, Throttled};
+use crate::startup::{ApplicationBaseUrl, SubscriptionTokenTtl};
+use crate::suppressions::is_suppressed;
+use crate::templates::{EmailTemplate, Templates};
+
+use super::subscriptions_confirm::prefers_json;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -17,73 +30,343 @@ pub struct FormData {
     name: String,
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+/// The configuration a submitted address is checked against.
+pub struct ValidationContext<'a> {
+    pub normalization: EmailNormalization,
+    pub domain_filter: &'a DomainFilter,
+}
+
+impl ValidationContext<'_> {
+    /// The checks any address submitted to a public form goes through
+    /// before we email it.
+    fn parse_email(
+        &self,
+        email: String,
+    ) -> Result<SubscriberEmail, SubscriberEmailError> {
+        SubscriberEmail::parse_with(email, self.normalization)
+            .and_then(|email| self.domain_filter.check(&email).map(|()| email))
+    }
+}
+
+impl TryFrom<(FormData, &ValidationContext<'_>)> for NewSubscriber {
+    type Error = Vec<FieldError>;
+
+    /// Every field is checked, so one response reports all of them.
+    fn try_from(
+        (form, context): (FormData, &ValidationContext<'_>),
+    ) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(form.name);
+        let email = context.parse_email(form.email);
+        match (name, email) {
+            (Ok(name), Ok(email)) => Ok(Self { name, email }),
+            (name, email) => {
+                let mut errors = Vec::new();
+                if let Err(e) = name {
+                    errors.push(FieldError::new(
+                        "name",
+                        e.code(),
+                        e.to_string(),
+                    ));
+                }
+                if let Err(e) = email {
+                    errors.push(email_field_error(&e));
+                }
+                Err(errors)
+            }
+        }
+    }
+}
+
+fn email_field_error(e: &SubscriberEmailError) -> FieldError {
+    FieldError::new("email", e.code(), e.to_string())
+}
+
+fn validation_error(
+    errors: Vec<FieldError>,
+    metrics: &Metrics,
+) -> SubscribeError {
+    let blocked = SubscriberEmailError::BlockedDomain.code();
+    if errors.iter().any(|e| e.code == blocked) {
+        metrics.subscriptions_blocked.inc();
+    }
+    SubscribeError::ValidationError(errors)
+}
 
-    fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { name, email })
+/// Nothing may be sent to a suppressed address until an admin lifts the
+/// suppression. Callers answer as they would for any other address: a
+/// refusal would tell anyone that the address bounced or complained.
+async fn check_suppression(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<bool, anyhow::Error> {
+    let suppressed = is_suppressed(transaction, email)
+        .await
+        .context("Failed to check the suppression list.")?;
+    if suppressed {
+        tracing::info!("Not emailing a suppressed address");
     }
+    Ok(suppressed)
 }
 
 #[tracing::instrument(
 name = "Adding a new subscriber",
-skip(form, db_pool, email_client, base_url),
+skip(
+    form,
+    db_pool,
+    base_url,
+    token_ttl,
+    normalization,
+    domain_filter,
+    metrics,
+    rate_limiter,
+    templates
+),
 fields(
 subscriber_email = % form.email,
 subscriber_name = % form.name
 )
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
     form: web::Form<FormData>,
     db_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    normalization: web::Data<EmailNormalization>,
+    domain_filter: web::Data<DomainFilter>,
+    metrics: web::Data<Metrics>,
+    rate_limiter: web::Data<RateLimiter>,
+    templates: web::Data<Templates>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber =
-        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let context = ValidationContext {
+        normalization: **normalization,
+        domain_filter: &domain_filter,
+    };
+    let new_subscriber = NewSubscriber::try_from((form.0, &context))
+        .map_err(|errors| validation_error(errors, &metrics))?;
+    rate_limiter
+        .check(Limit::SubscribePerEmail, new_subscriber.email.canonical())
+        .await?;
 
     let mut transaction = db_pool
         .begin()
         .await
         .context("Failed to connect to db pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    if check_suppression(&mut transaction, &new_subscriber.email).await? {
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    // Inserting first leaves no gap for a concurrent request with the same
+    // address to slip through: the loser waits for the winner to commit and
+    // then finds its row.
+    let inserted = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to create new subscriber to db.")?;
+    let subscriber_id = match inserted {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let subscriber = get_subscriber_by_email(
+                &mut transaction,
+                &new_subscriber.email,
+            )
+            .await
+            .context("Failed to look up existing subscriber by email.")?
+            .context("The subscriber holding this address is gone.")?;
+            match subscriber.status {
+                // Already confirmed: respond exactly as we would for a new
+                // address, so the endpoint can't be used to probe who is on
+                // the list.
+                SubscriberStatus::Confirmed => {
+                    return Ok(HttpResponse::Ok().finish());
+                }
+                SubscriberStatus::PendingConfirmation => subscriber.id,
+                // A bounced or complaining address starts over too, but
+                // nothing reaches it while it stays on the suppression list.
+                SubscriberStatus::Unsubscribed
+                | SubscriberStatus::Bounced
+                | SubscriberStatus::Complained => {
+                    transition_subscriber_status(
+                        &mut transaction,
+                        subscriber.id,
+                        SubscriberStatus::PendingConfirmation,
+                    )
+                    .await
+                    .context("Failed to move subscriber back to pending.")?;
+                    subscriber.id
+                }
+            }
+        }
+    };
+    let subscription_token =
+        issue_token(&mut transaction, subscriber_id, token_ttl.0).await?;
 
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, &subscription_token, subscriber_id)
-        .await
-        .context("Failed to create subscription confirmation token")?;
+    // Queued in the same transaction: the worker delivers it once the
+    // subscriber row is committed, and retries if the provider is down.
+    enqueue_confirmation_email(
+        &mut transaction,
+        &templates,
+        subscriber_id,
+        &new_subscriber.email,
+        new_subscriber.name.as_ref(),
+        &base_url,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to enqueue confirmation email")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit subscription transaction to db.")?;
+    if inserted.is_some() {
+        metrics.subscriptions_created.inc();
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+}
+
+#[tracing::instrument(
+name = "Resending a confirmation email",
+skip(
+    req,
+    form,
+    db_pool,
+    base_url,
+    token_ttl,
+    normalization,
+    domain_filter,
+    metrics,
+    rate_limiter,
+    templates
+),
+fields(subscriber_email = % form.email)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn resend_confirmation(
+    req: HttpRequest,
+    form: web::Form<ResendFormData>,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    normalization: web::Data<EmailNormalization>,
+    domain_filter: web::Data<DomainFilter>,
+    metrics: web::Data<Metrics>,
+    rate_limiter: web::Data<RateLimiter>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, SubscribeError> {
+    let context = ValidationContext {
+        normalization: **normalization,
+        domain_filter: &domain_filter,
+    };
+    let email = context
+        .parse_email(form.0.email)
+        .map_err(|e| validation_error(vec![email_field_error(&e)], &metrics))?;
+    // Resending sends email too, so it draws on the same quota.
+    rate_limiter
+        .check(Limit::SubscribePerEmail, email.canonical())
+        .await?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to connect to db pool")?;
+
+    if check_suppression(&mut transaction, &email).await? {
+        return resent(&req, &templates);
+    }
+
+    let subscriber = get_subscriber_by_email(&mut transaction, &email)
+        .await
+        .context("Failed to look up existing subscriber by email.")?;
+
+    // Unknown and already confirmed addresses get the same neutral answer.
+    let subscriber = match subscriber {
+        Some(s) if s.status == SubscriberStatus::PendingConfirmation => s,
+        _ => return resent(&req, &templates),
+    };
+
+    let subscription_token =
+        issue_token(&mut transaction, subscriber.id, token_ttl.0).await?;
 
-    // Send email.
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
+    enqueue_confirmation_email(
+        &mut transaction,
+        &templates,
+        subscriber.id,
+        &email,
+        &subscriber.name,
         &base_url,
         &subscription_token,
     )
     .await
-    .context("Failed to send confirmation email")?;
+    .context("Failed to enqueue confirmation email")?;
 
-    Ok(HttpResponse::Ok().finish())
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit subscription transaction to db.")?;
+
+    resent(&req, &templates)
+}
+
+/// The form on the expired link page posts here, so browsers get a page
+/// telling them to check their inbox; API clients get an empty 200.
+fn resent(
+    req: &HttpRequest,
+    templates: &Templates,
+) -> Result<HttpResponse, SubscribeError> {
+    if prefers_json(req) {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let body = templates.render("pages/confirmation/resent.html", ())?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
 }
 
+/// Issue a fresh token for each confirmation email. Only its hash is kept,
+/// so earlier tokens cannot be sent again; they stay valid until they
+/// expire.
+async fn issue_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    token_ttl: std::time::Duration,
+) -> Result<String, anyhow::Error> {
+    let token = generate_subscription_token();
+    store_token(transaction, &token, subscriber_id, token_ttl)
+        .await
+        .context("Failed to create subscription confirmation token")?;
+    Ok(token)
+}
+
+/// 43 alphanumeric characters carry just over 256 bits of entropy.
 fn generate_subscription_token() -> String {
+    generate_random_token(43)
+}
+
+fn generate_unsubscribe_token() -> String {
+    generate_random_token(25)
+}
+
+fn generate_random_token(length: usize) -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
-        .take(25)
+        .take(length)
         .collect()
 }
 
+/// What `subscription_tokens` stores in place of the token. Tokens are
+/// random enough that a plain SHA-256 cannot be reversed by guessing.
+pub fn hash_subscription_token(subscription_token: &str) -> String {
+    hex::encode(Sha256::digest(subscription_token.as_bytes()))
+}
+
 #[tracing::instrument(
 name = "Store subscription token in the database."
 skip(subscription_token, transaction)
@@ -92,12 +375,15 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
     subscriber_id: Uuid,
+    token_ttl: std::time::Duration,
 ) -> Result<(), StoreTokenError> {
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1,$2)"#,
-        subscription_token,
-        subscriber_id
+        r#"INSERT INTO subscription_tokens
+        (token_hash, subscriber_id, issued_at, expires_at)
+        VALUES ($1, $2, now(), now() + make_interval(secs => $3))"#,
+        hash_subscription_token(subscription_token),
+        subscriber_id,
+        token_ttl.as_secs_f64(),
     )
     .execute(transaction)
     .await
@@ -106,6 +392,67 @@ pub async fn store_token(
     Ok(())
 }
 
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    pub name: String,
+    pub status: SubscriberStatus,
+}
+
+#[tracing::instrument(
+    name = "Looking up subscriber by email",
+    skip(email, transaction)
+)]
+pub async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"SELECT id, name, status FROM subscriptions
+        WHERE email_canonical = $1"#,
+        email.canonical(),
+    )
+    .fetch_optional(transaction)
+    .await?;
+    match result {
+        Some(r) => {
+            let status = SubscriberStatus::try_from(r.status)
+                .map_err(anyhow::Error::msg)?;
+            Ok(Some(ExistingSubscriber {
+                id: r.id,
+                name: r.name,
+                status,
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Move a subscriber to `next`, but only from a status the lifecycle in
+/// `SubscriberStatus` allows. Returns `false` if the subscriber was not in
+/// an eligible status (or does not exist).
+#[tracing::instrument(name = "Transitioning subscriber status", skip(executor))]
+pub async fn transition_subscriber_status<'e>(
+    executor: impl PgExecutor<'e>,
+    subscriber_id: Uuid,
+    next: SubscriberStatus,
+) -> Result<bool, sqlx::Error> {
+    let eligible: Vec<String> = SubscriberStatus::predecessors_of(next)
+        .iter()
+        .map(|s| s.as_str().to_owned())
+        .collect();
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET status = $1
+        WHERE id = $2 AND status = ANY($3)"#,
+        next.as_str(),
+        subscriber_id,
+        &eligible[..],
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns `None` if a subscriber with the same canonical address exists.
 #[tracing::instrument(
 name = "Saving new subscriber details to DB."
 skip(new_subscriber, transaction)
@@ -113,47 +460,68 @@ skip(new_subscriber, transaction)
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let result = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions
+            (id, email, email_canonical, name, subscribed_at, status,
+            unsubscribe_token)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (email_canonical) DO NOTHING
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
+        new_subscriber.email.canonical(),
         new_subscriber.name.as_ref(),
         Utc::now(),
+        SubscriberStatus::PendingConfirmation.as_str(),
+        generate_unsubscribe_token(),
     )
     .execute(transaction)
     .await?;
-    Ok(subscriber_id)
+    Ok((result.rows_affected() == 1).then_some(subscriber_id))
 }
 
 #[tracing::instrument(
-    name = "Send confirmation email to subscriber",
-    skip(email_client, new_subscriber, base_url)
+    name = "Enqueue confirmation email to subscriber",
+    skip(
+        transaction,
+        templates,
+        recipient,
+        name,
+        base_url,
+        subscription_token
+    )
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    templates: &Templates,
+    subscriber_id: Uuid,
+    recipient: &SubscriberEmail,
+    name: &str,
     base_url: &ApplicationBaseUrl,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url.0, subscription_token
     );
-    let text_body = &format!("Blah!\n confirm: {}", confirmation_link);
-    let html_body = &format!(
-        "Blah blah blah, blah blah blah blah, blah!\
-         <br/> Click: <a href=\"{}\">here</a>.",
-        confirmation_link
-    );
+    let email = templates.render_email(
+        EmailTemplate::Confirmation,
+        minijinja::context! { name, confirmation_link },
+    )?;
 
-    email_client
-        .send_email(new_subscriber.email, "Hello world!", html_body, text_body)
-        .await
+    enqueue_email(
+        transaction,
+        recipient,
+        subscriber_id,
+        EmailTemplate::Confirmation,
+        &email,
+        &[],
+    )
+    .await?;
+    Ok(())
 }
 
 pub fn error_chain_fmt(
@@ -196,8 +564,10 @@ impl std::fmt::Display for StoreTokenError {
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
-    #[error("{0}")]
-    ValidationError(String),
+    #[error("The submitted form is invalid.")]
+    ValidationError(Vec<FieldError>),
+    #[error(transparent)]
+    RateLimited(#[from] Throttled),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -212,9 +582,22 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::RateLimited(e) => e.status_code(),
             SubscribeError::UnexpectedError(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let envelope = match self {
+            SubscribeError::ValidationError(details) => {
+                ErrorEnvelope::new("validation_error", self.to_string())
+                    .with_details(details.clone())
+            }
+            SubscribeError::RateLimited(e) => return e.error_response(),
+            SubscribeError::UnexpectedError(_) => ErrorEnvelope::internal(),
+        };
+        envelope.into_response(self.status_code())
+    }
 }
@@ -1,11 +1,22 @@
+use actix_web::http::header::{ContentType, ACCEPT};
 use actix_web::http::StatusCode;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::routes::error_chain_fmt;
+use crate::configuration::ConfirmationRedirects;
+use crate::delivery_worker::enqueue_email;
+use crate::domain::{EmailNormalization, SubscriberEmail, SubscriberStatus};
+use crate::errors::ErrorEnvelope;
+use crate::metrics::Metrics;
+use crate::routes::{
+    error_chain_fmt, hash_subscription_token, transition_subscriber_status,
+};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{EmailTemplate, Templates};
+use crate::utils::see_other;
 
 #[derive(Deserialize)]
 pub struct Parameters {
@@ -16,6 +27,8 @@ pub struct Parameters {
 pub enum ConfirmSubscriptionError {
     #[error("Invalid token.")]
     IncorrectTokenError,
+    #[error("Token has expired.")]
+    ExpiredTokenError,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -32,65 +45,345 @@ impl ResponseError for ConfirmSubscriptionError {
             ConfirmSubscriptionError::IncorrectTokenError => {
                 StatusCode::UNAUTHORIZED
             }
+            ConfirmSubscriptionError::ExpiredTokenError => StatusCode::GONE,
             ConfirmSubscriptionError::UnexpectedError(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let envelope = match self {
+            ConfirmSubscriptionError::IncorrectTokenError => {
+                ErrorEnvelope::new("invalid_token", self.to_string())
+            }
+            ConfirmSubscriptionError::ExpiredTokenError => {
+                ErrorEnvelope::new("expired_token", self.to_string())
+            }
+            ConfirmSubscriptionError::UnexpectedError(_) => {
+                ErrorEnvelope::internal()
+            }
+        };
+        envelope.into_response(self.status_code())
+    }
+}
+
+/// What a valid confirmation link did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationOutcome {
+    Confirmed,
+    /// The link, or another one for the same subscriber, was already used.
+    AlreadyConfirmed,
+}
+
+/// The pages a subscriber can land on from a confirmation link.
+#[derive(Debug, Clone, Copy)]
+enum LandingPage {
+    Confirmed,
+    AlreadyConfirmed,
+    Expired,
+    Invalid,
+}
+
+impl LandingPage {
+    fn template(&self) -> &'static str {
+        match self {
+            LandingPage::Confirmed => "pages/confirmation/confirmed.html",
+            LandingPage::AlreadyConfirmed => {
+                "pages/confirmation/already_confirmed.html"
+            }
+            LandingPage::Expired => "pages/confirmation/expired.html",
+            LandingPage::Invalid => "pages/confirmation/invalid.html",
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LandingPage::Confirmed | LandingPage::AlreadyConfirmed => {
+                StatusCode::OK
+            }
+            LandingPage::Expired => StatusCode::GONE,
+            LandingPage::Invalid => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn redirect<'a>(
+        &self,
+        redirects: &'a ConfirmationRedirects,
+    ) -> Option<&'a str> {
+        match self {
+            LandingPage::Confirmed => redirects.confirmed.as_deref(),
+            LandingPage::AlreadyConfirmed => {
+                redirects.already_confirmed.as_deref()
+            }
+            LandingPage::Expired => redirects.expired.as_deref(),
+            LandingPage::Invalid => redirects.invalid.as_deref(),
+        }
+    }
+
+    fn into_response(
+        self,
+        templates: &Templates,
+        redirects: &ConfirmationRedirects,
+    ) -> Result<HttpResponse, ConfirmSubscriptionError> {
+        if let Some(url) = self.redirect(redirects) {
+            return Ok(see_other(url));
+        }
+        let body = templates.render(self.template(), ())?;
+        Ok(HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(body))
+    }
 }
 
+/// People following the link from their inbox get a page (or a redirect);
+/// clients asking for JSON get the error envelope.
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Confirm a pending subscriber",
-    skip(parameters, db_pool)
+    skip(
+        req,
+        parameters,
+        db_pool,
+        base_url,
+        metrics,
+        templates,
+        redirects,
+        normalization
+    )
 )]
 pub async fn confirm(
+    req: HttpRequest,
     parameters: web::Query<Parameters>,
     db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    metrics: web::Data<Metrics>,
+    templates: web::Data<Templates>,
+    redirects: web::Data<ConfirmationRedirects>,
+    normalization: web::Data<EmailNormalization>,
 ) -> Result<HttpResponse, ConfirmSubscriptionError> {
-    let id =
-        get_subscriber_id_from_token(&parameters.subscription_token, &db_pool)
-            .await
-            .context("Failed to retrieve subscription id from token")?
-            .ok_or(ConfirmSubscriptionError::IncorrectTokenError)?;
+    let outcome = consume_token(
+        &parameters.subscription_token,
+        &db_pool,
+        &templates,
+        &base_url,
+        **normalization,
+    )
+    .await;
+    let page = match outcome {
+        Ok(ConfirmationOutcome::Confirmed) => {
+            metrics.subscriptions_confirmed.inc();
+            LandingPage::Confirmed
+        }
+        Ok(ConfirmationOutcome::AlreadyConfirmed) => {
+            LandingPage::AlreadyConfirmed
+        }
+        Err(e) if prefers_json(&req) => return Err(e),
+        Err(ConfirmSubscriptionError::ExpiredTokenError) => {
+            LandingPage::Expired
+        }
+        Err(ConfirmSubscriptionError::IncorrectTokenError) => {
+            LandingPage::Invalid
+        }
+        Err(e) => return Err(e),
+    };
+    page.into_response(&templates, &redirects)
+}
+
+pub(crate) fn prefers_json(req: &HttpRequest) -> bool {
+    let accept = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    accept.contains("application/json") && !accept.contains("text/html")
+}
 
-    confirm_subscriber(id, &db_pool)
+/// Use up `subscription_token`, confirm its subscriber and queue their
+/// welcome email, in one transaction so that a token can only ever confirm
+/// once.
+#[tracing::instrument(
+    name = "Consuming a subscription token",
+    skip(subscription_token, pool, templates, base_url)
+)]
+pub async fn consume_token(
+    subscription_token: &str,
+    pool: &PgPool,
+    templates: &Templates,
+    base_url: &ApplicationBaseUrl,
+    normalization: EmailNormalization,
+) -> Result<ConfirmationOutcome, ConfirmSubscriptionError> {
+    let mut transaction =
+        pool.begin().await.context("Failed to connect to db pool")?;
+    let token = get_token_for_update(&mut transaction, subscription_token)
         .await
-        .context(format!("Failed to confirm subscriber ID {}", id))?;
+        .context("Failed to retrieve subscription id from token")?
+        .ok_or(ConfirmSubscriptionError::IncorrectTokenError)?;
+    let status = SubscriberStatus::try_from(token.status.clone())
+        .map_err(anyhow::Error::msg)?;
 
-    Ok(HttpResponse::Ok().finish())
+    let outcome = match status {
+        // Whoever unsubscribed has to subscribe again, not reuse an old link.
+        SubscriberStatus::Unsubscribed
+        | SubscriberStatus::Bounced
+        | SubscriberStatus::Complained => {
+            return Err(ConfirmSubscriptionError::IncorrectTokenError)
+        }
+        SubscriberStatus::Confirmed => ConfirmationOutcome::AlreadyConfirmed,
+        SubscriberStatus::PendingConfirmation if token.used => {
+            return Err(ConfirmSubscriptionError::IncorrectTokenError)
+        }
+        SubscriberStatus::PendingConfirmation if token.expired => {
+            return Err(ConfirmSubscriptionError::ExpiredTokenError)
+        }
+        SubscriberStatus::PendingConfirmation => {
+            confirm_subscriber(&mut transaction, token.subscriber_id)
+                .await
+                .context(format!(
+                    "Failed to confirm subscriber ID {}",
+                    token.subscriber_id
+                ))?;
+            enqueue_welcome_email(
+                &mut transaction,
+                templates,
+                base_url,
+                &token,
+                normalization,
+            )
+            .await
+            .context("Failed to enqueue welcome email")?;
+            ConfirmationOutcome::Confirmed
+        }
+    };
+    if !token.used {
+        mark_token_used(&mut transaction, subscription_token)
+            .await
+            .context("Failed to mark subscription token as used")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit confirmation transaction to db.")?;
+    Ok(outcome)
 }
 
 #[tracing::instrument(
     name = "Mark subscriber as confirmed",
-    skip(subscriber_id, pool)
+    skip(subscriber_id, executor)
 )]
-pub async fn confirm_subscriber(
+pub async fn confirm_subscriber<'e>(
+    executor: impl PgExecutor<'e>,
     subscriber_id: Uuid,
-    pool: &PgPool,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id
+) -> Result<bool, sqlx::Error> {
+    transition_subscriber_status(
+        executor,
+        subscriber_id,
+        SubscriberStatus::Confirmed,
     )
-    .execute(pool)
-    .await?;
-    Ok(())
+    .await
+}
+
+pub struct StoredToken {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub unsubscribe_token: String,
+    pub status: String,
+    pub expired: bool,
+    pub used: bool,
 }
 
+/// Look a token up along with its subscriber's status, holding a lock on
+/// both rows until the transaction ends.
 #[tracing::instrument(
 name = "Getting subscriber id from a token"
-skip(subscription_token, pool)
+skip(subscription_token, transaction)
 )]
-pub async fn get_subscriber_id_from_token(
+pub async fn get_token_for_update(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-    pool: &PgPool,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
-        subscription_token
+) -> Result<Option<StoredToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        StoredToken,
+        r#"SELECT t.subscriber_id, s.email, s.name, s.unsubscribe_token,
+            s.status,
+            t.expires_at <= now() AS "expired!",
+            t.used_at IS NOT NULL AS "used!"
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.token_hash = $1
+        FOR UPDATE"#,
+        hash_subscription_token(subscription_token),
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(result)
+}
+
+#[tracing::instrument(
+    name = "Enqueue welcome email to subscriber",
+    skip(transaction, templates, base_url, subscriber)
+)]
+async fn enqueue_welcome_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    templates: &Templates,
+    base_url: &ApplicationBaseUrl,
+    subscriber: &StoredToken,
+    normalization: EmailNormalization,
+) -> Result<(), anyhow::Error> {
+    let recipient = match SubscriberEmail::parse_with(
+        subscriber.email.clone(),
+        normalization,
+    ) {
+        Ok(recipient) => recipient,
+        Err(error) => {
+            // Not worth undoing the confirmation over.
+            tracing::warn!(
+                error.cause_chain = ?error,
+                "Skipping the welcome email. \
+                The subscriber's stored email is invalid",
+            );
+            return Ok(());
+        }
+    };
+    let unsubscribe_link = format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        base_url.0, subscriber.unsubscribe_token
+    );
+    let email = templates.render_email(
+        EmailTemplate::Welcome,
+        minijinja::context! {
+            name => &subscriber.name,
+            unsubscribe_link,
+        },
+    )?;
+    enqueue_email(
+        transaction,
+        &recipient,
+        subscriber.subscriber_id,
+        EmailTemplate::Welcome,
+        &email,
+        &[],
     )
-        .fetch_optional(pool)
-        .await?;
+    .await?;
+    Ok(())
+}
 
-    Ok(result.map(|r| r.subscriber_id))
+#[tracing::instrument(
+    name = "Marking a subscription token as used",
+    skip(subscription_token, transaction)
+)]
+async fn mark_token_used(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscription_tokens SET used_at = now()
+        WHERE token_hash = $1"#,
+        hash_subscription_token(subscription_token),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
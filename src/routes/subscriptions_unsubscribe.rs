@@ -0,0 +1,336 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use minijinja::context;
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::SubscriberStatus;
+use crate::email_client::EmailHeader;
+use crate::errors::ErrorEnvelope;
+use crate::routes::{error_chain_fmt, transition_subscriber_status};
+use crate::templates::Templates;
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    unsubscribe_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct OneClickParameters {
+    subscriber_id: Uuid,
+    signature: String,
+}
+
+/// Builds and checks the signed per-subscriber URLs that mailbox providers
+/// POST to when the reader clicks their own "unsubscribe" button
+/// (RFC 8058). The signature stands in for a session: it proves we issued
+/// the URL, so anyone holding it may unsubscribe that subscriber.
+pub struct OneClickUnsubscribe {
+    base_url: String,
+    key: Secret<String>,
+}
+
+impl OneClickUnsubscribe {
+    pub fn new(base_url: String, key: Secret<String>) -> Self {
+        Self { base_url, key }
+    }
+
+    pub fn url(&self, subscriber_id: Uuid) -> String {
+        let signature = self.mac(subscriber_id).finalize().into_bytes();
+        format!(
+            "{}/subscriptions/unsubscribe/one-click?subscriber_id={}&signature={}",
+            self.base_url,
+            subscriber_id,
+            hex::encode(signature)
+        )
+    }
+
+    /// `List-Unsubscribe` and `List-Unsubscribe-Post` for an email to
+    /// `subscriber_id`.
+    pub fn headers(&self, subscriber_id: Uuid) -> Vec<EmailHeader> {
+        vec![
+            EmailHeader {
+                name: "List-Unsubscribe".into(),
+                value: format!("<{}>", self.url(subscriber_id)),
+            },
+            EmailHeader {
+                name: "List-Unsubscribe-Post".into(),
+                value: "List-Unsubscribe=One-Click".into(),
+            },
+        ]
+    }
+
+    fn verify(&self, subscriber_id: Uuid, signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        self.mac(subscriber_id).verify_slice(&signature).is_ok()
+    }
+
+    fn mac(&self, subscriber_id: Uuid) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+                .expect("HMAC accepts keys of any length");
+        // Keeps these signatures from being valid anywhere else the key is
+        // used.
+        mac.update(b"one-click-unsubscribe:");
+        mac.update(subscriber_id.as_bytes());
+        mac
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("Invalid token.")]
+    IncorrectTokenError,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::IncorrectTokenError => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let envelope = match self {
+            UnsubscribeError::IncorrectTokenError => {
+                ErrorEnvelope::new("invalid_token", self.to_string())
+            }
+            UnsubscribeError::UnexpectedError(_) => ErrorEnvelope::internal(),
+        };
+        envelope.into_response(self.status_code())
+    }
+}
+
+/// Following a link must not change state (mail scanners prefetch links),
+/// so GET only renders a form that POSTs back to the same URL.
+#[tracing::instrument(
+    name = "Show unsubscribe form",
+    skip(parameters, db_pool, templates)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    get_subscriber_id_from_unsubscribe_token(
+        &parameters.unsubscribe_token,
+        &db_pool,
+    )
+    .await
+    .context("Failed to retrieve subscriber id from unsubscribe token")?
+    .ok_or(UnsubscribeError::IncorrectTokenError)?;
+
+    unsubscribe_form_page(
+        &templates,
+        &format!(
+            "/subscriptions/unsubscribe?unsubscribe_token={}",
+            parameters.unsubscribe_token
+        ),
+    )
+}
+
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, db_pool, templates)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let id = get_subscriber_id_from_unsubscribe_token(
+        &parameters.unsubscribe_token,
+        &db_pool,
+    )
+    .await
+    .context("Failed to retrieve subscriber id from unsubscribe token")?
+    .ok_or(UnsubscribeError::IncorrectTokenError)?;
+
+    // Unsubscribing twice is not an error: the subscriber ends up where
+    // they asked to be either way.
+    transition_subscriber_status(
+        db_pool.get_ref(),
+        id,
+        SubscriberStatus::Unsubscribed,
+    )
+    .await
+    .context(format!("Failed to unsubscribe subscriber ID {}", id))?;
+
+    unsubscribed_page(&templates)
+}
+
+/// The `List-Unsubscribe` URL opened in a browser. Like `unsubscribe_form`,
+/// it only renders a form that POSTs back.
+#[tracing::instrument(
+    name = "Show one-click unsubscribe form",
+    skip(parameters, one_click, templates),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn one_click_unsubscribe_form(
+    parameters: web::Query<OneClickParameters>,
+    one_click: web::Data<OneClickUnsubscribe>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    if !one_click.verify(parameters.subscriber_id, &parameters.signature) {
+        return Err(UnsubscribeError::IncorrectTokenError);
+    }
+    unsubscribe_form_page(
+        &templates,
+        &format!(
+            "/subscriptions/unsubscribe/one-click?subscriber_id={}&signature={}",
+            parameters.subscriber_id, parameters.signature
+        ),
+    )
+}
+
+/// The POST a mailbox provider sends for a `List-Unsubscribe-Post` header.
+/// It carries no cookies and a `List-Unsubscribe=One-Click` body, which is
+/// not checked: the signed URL alone authorizes it.
+#[tracing::instrument(
+    name = "One-click unsubscribe",
+    skip(parameters, db_pool, one_click, templates),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn one_click_unsubscribe(
+    parameters: web::Query<OneClickParameters>,
+    db_pool: web::Data<PgPool>,
+    one_click: web::Data<OneClickUnsubscribe>,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let id = parameters.subscriber_id;
+    if !one_click.verify(id, &parameters.signature) {
+        return Err(UnsubscribeError::IncorrectTokenError);
+    }
+    transition_subscriber_status(
+        db_pool.get_ref(),
+        id,
+        SubscriberStatus::Unsubscribed,
+    )
+    .await
+    .context(format!("Failed to unsubscribe subscriber ID {}", id))?;
+
+    unsubscribed_page(&templates)
+}
+
+/// `action` is the URL the form POSTs back to.
+fn unsubscribe_form_page(
+    templates: &Templates,
+    action: &str,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let body =
+        templates.render("pages/unsubscribe/form.html", context! { action })?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+fn unsubscribed_page(
+    templates: &Templates,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let body = templates.render("pages/unsubscribe/unsubscribed.html", ())?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(
+    name = "Getting subscriber id from an unsubscribe token",
+    skip(unsubscribe_token, pool)
+)]
+pub async fn get_subscriber_id_from_unsubscribe_token(
+    unsubscribe_token: &str,
+    pool: &PgPool,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE unsubscribe_token = $1"#,
+        unsubscribe_token
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result.map(|r| r.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::OneClickUnsubscribe;
+
+    fn one_click(key: &str) -> OneClickUnsubscribe {
+        OneClickUnsubscribe::new(
+            "https://example.com".into(),
+            Secret::new(key.into()),
+        )
+    }
+
+    fn signature(url: &str) -> &str {
+        url.split_once("signature=").unwrap().1
+    }
+
+    #[test]
+    fn a_signature_we_issued_is_accepted() {
+        let one_click = one_click("secret");
+        let id = Uuid::new_v4();
+
+        let url = one_click.url(id);
+
+        assert!(one_click.verify(id, signature(&url)));
+    }
+
+    #[test]
+    fn a_signature_for_another_subscriber_is_rejected() {
+        let one_click = one_click("secret");
+
+        let url = one_click.url(Uuid::new_v4());
+
+        assert!(!one_click.verify(Uuid::new_v4(), signature(&url)));
+    }
+
+    #[test]
+    fn a_signature_made_with_another_key_is_rejected() {
+        let id = Uuid::new_v4();
+
+        let url = one_click("another secret").url(id);
+
+        assert!(!one_click("secret").verify(id, signature(&url)));
+    }
+
+    #[test]
+    fn a_malformed_signature_is_rejected() {
+        assert!(!one_click("secret").verify(Uuid::new_v4(), "not-hex"));
+    }
+
+    #[test]
+    fn the_headers_ask_for_one_click_unsubscribe() {
+        let id = Uuid::new_v4();
+        let one_click = one_click("secret");
+
+        let headers = one_click.headers(id);
+
+        assert_eq!(headers[0].name, "List-Unsubscribe");
+        assert_eq!(headers[0].value, format!("<{}>", one_click.url(id)));
+        assert_eq!(headers[1].name, "List-Unsubscribe-Post");
+        assert_eq!(headers[1].value, "List-Unsubscribe=One-Click");
+    }
+}
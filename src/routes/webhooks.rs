@@ -0,0 +1,296 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::http::{header::HeaderValue, StatusCode};
+use actix_web::{web, HttpResponse, ResponseError};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use base64::Engine;
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+
+use crate::configuration::WebhookSettings;
+use crate::delivery_worker::EmailMessageStatus;
+use crate::domain::{EmailNormalization, SubscriberEmail, SubscriberStatus};
+use crate::errors::ErrorEnvelope;
+use crate::routes::{
+    error_chain_fmt, get_subscriber_by_email, transition_subscriber_status,
+};
+use crate::suppressions::{suppress, SuppressionReason};
+
+/// Bounce types after which Postmark itself stops sending to an address.
+/// Soft bounces and auto-replies are left alone.
+const PERMANENT_BOUNCES: &[&str] = &["HardBounce", "BadEmailAddress"];
+
+/// The Postmark webhook events we act on. Anything else is acknowledged
+/// and ignored, so enabling more events upstream breaks nothing.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType")]
+pub enum PostmarkEvent {
+    Bounce(BounceEvent),
+    SpamComplaint(ComplaintEvent),
+    Delivery(DeliveryEvent),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct BounceEvent {
+    #[serde(rename = "Type")]
+    kind: String,
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    email: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ComplaintEvent {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    email: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeliveryEvent {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+/// A report that the recipient must not be emailed again.
+#[derive(Debug, Clone, Copy)]
+enum Report {
+    Bounce,
+    Complaint,
+}
+
+impl Report {
+    fn reason(&self) -> SuppressionReason {
+        match self {
+            Report::Bounce => SuppressionReason::Bounce,
+            Report::Complaint => SuppressionReason::Complaint,
+        }
+    }
+
+    fn message_status(&self) -> EmailMessageStatus {
+        match self {
+            Report::Bounce => EmailMessageStatus::Bounced,
+            Report::Complaint => EmailMessageStatus::Complained,
+        }
+    }
+
+    fn subscriber_status(&self) -> SubscriberStatus {
+        match self {
+            Report::Bounce => SubscriberStatus::Bounced,
+            Report::Complaint => SubscriberStatus::Complained,
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Missing or invalid webhook credentials.")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::Unauthorized => StatusCode::UNAUTHORIZED,
+            WebhookError::UnexpectedError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::Unauthorized => {
+                let mut response =
+                    ErrorEnvelope::new("unauthorized", self.to_string())
+                        .into_response(self.status_code());
+                response.headers_mut().insert(
+                    WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="webhooks""#),
+                );
+                response
+            }
+            WebhookError::UnexpectedError(_) => {
+                ErrorEnvelope::internal().into_response(self.status_code())
+            }
+        }
+    }
+}
+
+/// Rejects requests without the credentials in `WebhookSettings`, before
+/// their body is read.
+pub async fn require_webhook_credentials(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let settings = req
+        .app_data::<web::Data<WebhookSettings>>()
+        .expect("WebhookSettings are registered as app data");
+    // Digests are compared, both of them every time, so the time taken
+    // says nothing about how much of either credential matched.
+    let username = Sha256::digest(settings.username.as_bytes());
+    let password = Sha256::digest(settings.password.expose_secret().as_bytes());
+    let authorized = basic_credentials(&req).is_some_and(|(user, pass)| {
+        (Sha256::digest(user.as_bytes()) == username)
+            & (Sha256::digest(pass.as_bytes()) == password)
+    });
+    if !authorized {
+        return Err(WebhookError::Unauthorized.into());
+    }
+    next.call(req).await
+}
+
+fn basic_credentials(req: &ServiceRequest) -> Option<(String, String)> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_owned(), password.to_owned()))
+}
+
+/// Bounce, spam complaint and delivery events from Postmark. A permanent
+/// bounce or a complaint suppresses the address and marks its subscriber;
+/// a delivery is recorded on the message.
+#[tracing::instrument(
+    name = "Handle a Postmark webhook event",
+    skip(event, db_pool, normalization)
+)]
+pub async fn postmark_webhook(
+    event: web::Json<PostmarkEvent>,
+    db_pool: web::Data<PgPool>,
+    normalization: web::Data<EmailNormalization>,
+) -> Result<HttpResponse, WebhookError> {
+    match event.into_inner() {
+        PostmarkEvent::Bounce(bounce)
+            if PERMANENT_BOUNCES.contains(&bounce.kind.as_str()) =>
+        {
+            suppress_recipient(
+                &db_pool,
+                **normalization,
+                &bounce.message_id,
+                &bounce.email,
+                Report::Bounce,
+            )
+            .await?;
+        }
+        PostmarkEvent::Bounce(bounce) => {
+            tracing::info!(kind = %bounce.kind, "Ignoring a transient bounce");
+        }
+        PostmarkEvent::SpamComplaint(complaint) => {
+            suppress_recipient(
+                &db_pool,
+                **normalization,
+                &complaint.message_id,
+                &complaint.email,
+                Report::Complaint,
+            )
+            .await?;
+        }
+        PostmarkEvent::Delivery(delivery) => {
+            // A late delivery event must not hide an earlier bounce.
+            update_message_status(
+                db_pool.get_ref(),
+                &delivery.message_id,
+                EmailMessageStatus::Delivered,
+                &[EmailMessageStatus::Sent],
+            )
+            .await
+            .context("Failed to record a delivery")?;
+        }
+        PostmarkEvent::Other => {}
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(
+    name = "Suppress a recipient reported by the provider",
+    skip(db_pool, normalization, email)
+)]
+async fn suppress_recipient(
+    db_pool: &PgPool,
+    normalization: EmailNormalization,
+    message_id: &str,
+    email: &str,
+    report: Report,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to connect to db pool")?;
+    let status = report.message_status();
+    update_message_status(&mut transaction, message_id, status, &[])
+        .await
+        .context("Failed to update the reported message")?;
+    match SubscriberEmail::parse_with(email.to_owned(), normalization) {
+        Ok(email) => {
+            suppress(&mut transaction, &email, report.reason())
+                .await
+                .context("Failed to add the address to the suppression list")?;
+            let subscriber = get_subscriber_by_email(&mut transaction, &email)
+                .await
+                .context("Failed to look up the reported subscriber")?;
+            if let Some(subscriber) = subscriber {
+                transition_subscriber_status(
+                    &mut transaction,
+                    subscriber.id,
+                    report.subscriber_status(),
+                )
+                .await
+                .context("Failed to update the reported subscriber")?;
+            }
+        }
+        Err(e) => {
+            tracing::warn!(
+                error.message = %e,
+                "The provider reported an address we cannot parse",
+            );
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the reported event")?;
+    Ok(())
+}
+
+/// Move the message the provider knows as `provider_message_id` to
+/// `status`, if it is currently in one of `from` (any status if empty).
+async fn update_message_status<'e>(
+    executor: impl PgExecutor<'e>,
+    provider_message_id: &str,
+    status: EmailMessageStatus,
+    from: &[EmailMessageStatus],
+) -> Result<(), sqlx::Error> {
+    let from: Vec<String> =
+        from.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"UPDATE email_messages SET status = $2, updated_at = now()
+        WHERE provider_message_id = $1
+            AND (cardinality($3::text[]) = 0 OR status = ANY($3))"#,
+        provider_message_id,
+        status.as_str(),
+        &from[..],
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use actix_session::storage::{
+    LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::PgPool;
+
+type SessionState = HashMap<String, String>;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStoreKind {
+    Postgres,
+    Memory,
+}
+
+/// The session backend picked in configuration.
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Postgres(PgSessionStore),
+    Memory(InMemorySessionStore),
+}
+
+impl AppSessionStore {
+    pub fn new(kind: SessionStoreKind, db_pool: PgPool) -> Self {
+        match kind {
+            SessionStoreKind::Postgres => {
+                Self::Postgres(PgSessionStore::new(db_pool))
+            }
+            SessionStoreKind::Memory => {
+                Self::Memory(InMemorySessionStore::default())
+            }
+        }
+    }
+
+    fn inner(&self) -> &dyn SessionStore {
+        match self {
+            Self::Postgres(store) => store,
+            Self::Memory(store) => store,
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for AppSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<SessionState>, LoadError> {
+        self.inner().load(session_key).await
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        self.inner().save(session_state, ttl).await
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        self.inner().update(session_key, session_state, ttl).await
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        self.inner().update_ttl(session_key, ttl).await
+    }
+
+    async fn delete(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<(), anyhow::Error> {
+        self.inner().delete(session_key).await
+    }
+}
+
+fn generate_session_key() -> SessionKey {
+    let key: String =
+        std::iter::repeat_with(|| thread_rng().sample(Alphanumeric))
+            .map(char::from)
+            .take(64)
+            .collect();
+    key.try_into()
+        .expect("A 64 character key is a valid session key")
+}
+
+/// Sessions in the `sessions` table, one JSON-encoded row per session.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    db_pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PgSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"SELECT state FROM sessions
+            WHERE session_key = $1 AND expires_at > now()"#,
+            session_key.as_ref(),
+        )
+        .fetch_optional(&self.db_pool)
+        .await
+        .context("Failed to load session state")
+        .map_err(LoadError::Other)?;
+        row.map(|r| serde_json::from_str(&r.state))
+            .transpose()
+            .context("Failed to deserialize session state")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize session state")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+        sqlx::query!(
+            r#"INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, now() + make_interval(secs => $3))"#,
+            session_key.as_ref(),
+            state,
+            ttl.as_seconds_f64(),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to save session state")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .context("Failed to serialize session state")
+            .map_err(UpdateError::Serialization)?;
+        let result = sqlx::query!(
+            r#"UPDATE sessions
+            SET state = $2, expires_at = now() + make_interval(secs => $3)
+            WHERE session_key = $1"#,
+            session_key.as_ref(),
+            state,
+            ttl.as_seconds_f64(),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to update session state")
+        .map_err(UpdateError::Other)?;
+        if result.rows_affected() == 0 {
+            // The session expired or was deleted in the meantime.
+            return self
+                .save(session_state, ttl)
+                .await
+                .map_err(|e| UpdateError::Other(anyhow::anyhow!(e)));
+        }
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"UPDATE sessions
+            SET expires_at = now() + make_interval(secs => $2)
+            WHERE session_key = $1"#,
+            session_key.as_ref(),
+            ttl.as_seconds_f64(),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to update session TTL")?;
+        Ok(())
+    }
+
+    async fn delete(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1"#,
+            session_key.as_ref(),
+        )
+        .execute(&self.db_pool)
+        .await
+        .context("Failed to delete session")?;
+        Ok(())
+    }
+}
+
+/// Delete the sessions that have expired. `load` already ignores them;
+/// this keeps the table from growing with every abandoned login. Returns
+/// the number of rows deleted.
+#[tracing::instrument(name = "Deleting expired sessions", skip_all)]
+pub async fn delete_expired_sessions(
+    pool: &PgPool,
+) -> Result<u64, sqlx::Error> {
+    let result =
+        sqlx::query!(r#"DELETE FROM sessions WHERE expires_at <= now()"#)
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected())
+}
+
+/// Process-local sessions for development and tests. Sessions are lost on
+/// restart and are not shared between instances.
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<Mutex<HashMap<String, (SessionState, OffsetDateTime)>>>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for InMemorySessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions
+            .get(session_key.as_ref())
+            .filter(|(_, expires_at)| *expires_at > OffsetDateTime::now_utc())
+            .map(|(state, _)| state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let expires_at = OffsetDateTime::now_utc() + *ttl;
+        self.sessions.lock().unwrap().insert(
+            session_key.as_ref().to_owned(),
+            (session_state, expires_at),
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let expires_at = OffsetDateTime::now_utc() + *ttl;
+        self.sessions.lock().unwrap().insert(
+            session_key.as_ref().to_owned(),
+            (session_state, expires_at),
+        );
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        if let Some((_, expires_at)) =
+            self.sessions.lock().unwrap().get_mut(session_key.as_ref())
+        {
+            *expires_at = OffsetDateTime::now_utc() + *ttl;
+        }
+        Ok(())
+    }
+
+    async fn delete(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<(), anyhow::Error> {
+        self.sessions.lock().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
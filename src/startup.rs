@@ -1,39 +1,98 @@
 use std::net::TcpListener;
+use std::sync::Arc;
 
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
+use actix_web_flash_messages::storage::CookieMessageStore;
+use actix_web_flash_messages::FlashMessagesFramework;
+use actix_web_lab::middleware::from_fn;
+use secrecy::ExposeSecret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, subscribe};
+use crate::authentication::reject_anonymous_users;
+use crate::canonical_emails::CanonicalEmailBackfill;
+use crate::configuration::{
+    ApplicationSettings, DatabaseSettings, ReadinessCheck, Settings,
+};
+use crate::delivery_worker::DeliveryWorker;
+use crate::domain_filter::{DomainFilter, DomainFilterReloader};
+use crate::email_client::{
+    EmailClient, MeteredEmailClient, SuppressingEmailClient,
+};
+use crate::errors::{form_config, json_config, query_config, scope_request_id};
+use crate::metrics::{metrics_endpoint, track_http_requests, Metrics};
+use crate::rate_limit::{
+    limit_confirm_per_ip, limit_subscribe_per_ip, RateLimiter,
+};
+use crate::routes::{
+    admin_dashboard, change_password, change_password_form, confirm,
+    delete_email_domain_rule, delete_suppression, health_check,
+    list_email_domain_rules, list_suppressions, log_out, login, login_form,
+    one_click_unsubscribe, one_click_unsubscribe_form, postmark_webhook,
+    publish_newsletter, readiness, require_webhook_credentials,
+    resend_confirmation, save_email_domain_rule, save_suppression, subscribe,
+    unsubscribe, unsubscribe_form, OneClickUnsubscribe,
+};
+use crate::session_store::AppSessionStore;
+use crate::sweeper::ExpiredRowSweeper;
+use crate::templates::Templates;
 
 pub struct Application {
     port: u16,
     server: Server,
+    metrics_port: u16,
+    metrics_server: Server,
+    delivery_worker: DeliveryWorker,
+    domain_filter_reloader: DomainFilterReloader,
+    canonical_email_backfill: CanonicalEmailBackfill,
+    expired_row_sweeper: ExpiredRowSweeper,
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct SubscriptionTokenTtl(pub std::time::Duration);
+
 impl Application {
     pub async fn build(config: Settings) -> Result<Self, std::io::Error> {
         // DB Pool setup
         let db_pool = get_connection_pool(&config.database);
+        let normalization = config.application.email_normalization;
+        let canonical_email_backfill =
+            CanonicalEmailBackfill::new(db_pool.clone(), normalization);
+
+        // Outbox worker, sharing the pool with the HTTP server
+        let email_check = config.email_client.readiness_check;
+        let metrics = Metrics::new().map_err(std::io::Error::other)?;
+        let provider = config.email_client.provider.as_str();
+        let email_client: EmailClient = Arc::new(MeteredEmailClient::new(
+            config.email_client.client(),
+            provider,
+            metrics.clone(),
+        ));
+        // Outside the metered client: a suppressed send never reaches the
+        // provider, so it is not counted as one.
+        let email_client: EmailClient = Arc::new(SuppressingEmailClient::new(
+            email_client,
+            db_pool.clone(),
+        ));
+        let delivery_worker = DeliveryWorker::new(
+            db_pool.clone(),
+            email_client.clone(),
+            normalization,
+        );
 
-        // Email client setup
-        let sender_mail = config
-            .email_client
-            .sender()
-            .expect("Invalid sender email address in config.");
-        let timeout = config.email_client.timeout();
-        let email_client = EmailClient::new(
-            config.email_client.base_url,
-            sender_mail,
-            config.email_client.authorization_token,
-            timeout,
+        // Domain rules edited through the admin API, kept in sync with the DB
+        let domain_filter = config.application.domain_filter.filter();
+        let domain_filter_reloader = DomainFilterReloader::new(
+            domain_filter.clone(),
+            db_pool.clone(),
+            config.application.domain_filter.reload_interval(),
         );
+        let expired_row_sweeper = ExpiredRowSweeper::new(db_pool.clone());
 
         // Finally, build and **return** the server
         let addr_str =
@@ -41,17 +100,62 @@ impl Application {
         let listener = TcpListener::bind(addr_str)?;
         let port = listener.local_addr().unwrap().port();
 
-        let server =
-            run(listener, db_pool, email_client, config.application.base_url)?;
-        Ok(Self { server, port })
+        let metrics_listener = TcpListener::bind(format!(
+            "{}:{}",
+            config.metrics.host, config.metrics.port
+        ))?;
+        let metrics_port = metrics_listener.local_addr().unwrap().port();
+        let metrics_server =
+            run_metrics(metrics_listener, db_pool.clone(), metrics.clone())?;
+
+        let server = run(
+            listener,
+            db_pool,
+            email_client,
+            email_check,
+            metrics,
+            domain_filter,
+            config.application,
+        )?;
+        Ok(Self {
+            server,
+            port,
+            metrics_server,
+            metrics_port,
+            delivery_worker,
+            domain_filter_reloader,
+            canonical_email_backfill,
+            expired_row_sweeper,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    pub fn metrics_port(&self) -> u16 {
+        self.metrics_port
+    }
+
+    /// Serve HTTP and drain the delivery queue until either one stops.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        tokio::spawn(self.canonical_email_backfill.run_until_done());
+        tokio::select! {
+            outcome = self.server => outcome,
+            outcome = self.metrics_server => outcome,
+            () = self.domain_filter_reloader.run_until_stopped() => Ok(()),
+            () = self.expired_row_sweeper.run_until_stopped() => Ok(()),
+            outcome = self.delivery_worker.run_until_stopped() => {
+                if let Err(e) = outcome {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Delivery worker failed",
+                    );
+                }
+                Ok(())
+            }
+        }
     }
 }
 
@@ -65,23 +169,153 @@ fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
+    email_check: ReadinessCheck,
+    metrics: Metrics,
+    domain_filter: DomainFilter,
+    config: ApplicationSettings,
 ) -> Result<Server, std::io::Error> {
+    let token_ttl = config.token_ttl();
+    let session_store =
+        AppSessionStore::new(config.session_store, db_pool.clone());
+    let rate_limiter =
+        web::Data::new(RateLimiter::new(config.rate_limit, db_pool.clone()));
+    // A broken template fails the build rather than a request.
+    let templates = Templates::load(config.templates_dir.as_deref())
+        .map_err(std::io::Error::other)?;
+    let templates = web::Data::new(templates);
+    let confirmation_redirects = web::Data::new(config.confirmation_redirects);
+    let email_normalization = web::Data::new(config.email_normalization);
+    let webhooks = web::Data::new(config.webhooks);
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_check = web::Data::new(email_check);
+    let metrics = web::Data::new(metrics);
+    let domain_filter = web::Data::new(domain_filter);
+    let one_click = web::Data::new(OneClickUnsubscribe::new(
+        config.base_url.clone(),
+        config.unsubscribe_signing_key.clone(),
+    ));
+    let base_url = web::Data::new(ApplicationBaseUrl(config.base_url));
+    let token_ttl = web::Data::new(SubscriptionTokenTtl(token_ttl));
+    let secret_key =
+        Key::try_from(config.hmac_secret.expose_secret().as_bytes())
+            .map_err(std::io::Error::other)?;
+    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    let message_framework =
+        FlashMessagesFramework::builder(message_store).build();
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
+            .wrap(from_fn(scope_request_id))
+            .wrap(from_fn(track_http_requests))
             .wrap(TracingLogger::default())
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/health/live", web::get().to(health_check))
+            .route("/health/ready", web::get().to(readiness))
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(from_fn(limit_subscribe_per_ip))
+                    .route(web::post().to(subscribe)),
+            )
+            .service(
+                web::resource("/subscriptions/confirm")
+                    .wrap(from_fn(limit_confirm_per_ip))
+                    .route(web::get().to(confirm)),
+            )
+            .service(
+                web::resource("/subscriptions/confirm/resend")
+                    .wrap(from_fn(limit_subscribe_per_ip))
+                    .route(web::post().to(resend_confirmation)),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/unsubscribe/one-click",
+                web::get().to(one_click_unsubscribe_form),
+            )
+            .route(
+                "/subscriptions/unsubscribe/one-click",
+                web::post().to(one_click_unsubscribe),
+            )
+            .service(
+                web::resource("/webhooks/postmark")
+                    .wrap(from_fn(require_webhook_credentials))
+                    .route(web::post().to(postmark_webhook)),
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out))
+                    .route("/newsletters", web::post().to(publish_newsletter))
+                    .route(
+                        "/email-domains",
+                        web::get().to(list_email_domain_rules),
+                    )
+                    .route(
+                        "/email-domains",
+                        web::post().to(save_email_domain_rule),
+                    )
+                    .route(
+                        "/email-domains/{domain}",
+                        web::delete().to(delete_email_domain_rule),
+                    )
+                    .route("/suppressions", web::get().to(list_suppressions))
+                    .route("/suppressions", web::post().to(save_suppression))
+                    .route(
+                        "/suppressions/{email}",
+                        web::delete().to(delete_suppression),
+                    ),
+            )
+            .app_data(form_config())
+            .app_data(query_config())
+            .app_data(json_config())
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_check.clone())
+            .app_data(metrics.clone())
+            .app_data(domain_filter.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(templates.clone())
+            .app_data(confirmation_redirects.clone())
             .app_data(base_url.clone())
+            .app_data(one_click.clone())
+            .app_data(token_ttl.clone())
+            .app_data(email_normalization.clone())
+            .app_data(webhooks.clone())
     })
     .listen(listener)?
     .run();
 
     Ok(server)
 }
+
+/// `/metrics` on its own listener, separate from the public routes.
+fn run_metrics(
+    listener: TcpListener,
+    db_pool: PgPool,
+    metrics: Metrics,
+) -> Result<Server, std::io::Error> {
+    let db_pool = web::Data::new(db_pool);
+    let metrics = web::Data::new(metrics);
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/metrics", web::get().to(metrics_endpoint))
+            .app_data(db_pool.clone())
+            .app_data(metrics.clone())
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+
+use sqlx::PgExecutor;
+
+use crate::domain::SubscriberEmail;
+
+/// Why an address is on the suppression list. Bounces and complaints are
+/// reported by the provider; the others are added by an admin.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    Bounce,
+    Complaint,
+    Manual,
+    LegalRequest,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionReason::Bounce => "bounce",
+            SuppressionReason::Complaint => "complaint",
+            SuppressionReason::Manual => "manual",
+            SuppressionReason::LegalRequest => "legal_request",
+        }
+    }
+}
+
+/// Add `email` to the suppression list. An address already on it keeps
+/// its original reason and timestamp.
+#[tracing::instrument(name = "Suppressing an email address", skip(executor))]
+pub async fn suppress<'e>(
+    executor: impl PgExecutor<'e>,
+    email: &SubscriberEmail,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO suppressions (email_canonical, reason)
+        VALUES ($1, $2)
+        ON CONFLICT (email_canonical) DO NOTHING"#,
+        email.canonical(),
+        reason.as_str(),
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+pub async fn is_suppressed<'e>(
+    executor: impl PgExecutor<'e>,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let suppressed =
+        suppressed_among(executor, &[email.canonical().to_owned()]).await?;
+    Ok(!suppressed.is_empty())
+}
+
+/// The canonical addresses among `canonicals` that are suppressed.
+#[tracing::instrument(name = "Checking the suppression list", skip_all)]
+pub async fn suppressed_among<'e>(
+    executor: impl PgExecutor<'e>,
+    canonicals: &[String],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT email_canonical FROM suppressions
+        WHERE email_canonical = ANY($1)"#,
+        canonicals,
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.email_canonical).collect())
+}
+
+/// Take `email` off the suppression list. Returns `false` if it was not on
+/// it.
+#[tracing::instrument(name = "Lifting a suppression", skip(executor))]
+pub async fn lift_suppression<'e>(
+    executor: impl PgExecutor<'e>,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM suppressions WHERE email_canonical = $1"#,
+        email.canonical(),
+    )
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
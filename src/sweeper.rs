@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::rate_limit::delete_expired_windows;
+use crate::session_store::delete_expired_sessions;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Periodically deletes expired rows from the Postgres-backed stores, as
+/// the in-memory rate limiter does with its own windows.
+pub struct ExpiredRowSweeper {
+    db_pool: PgPool,
+}
+
+impl ExpiredRowSweeper {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool }
+    }
+
+    pub async fn run_until_stopped(self) {
+        loop {
+            if let Err(e) = sweep_expired_rows(&self.db_pool).await {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Failed to sweep expired rows",
+                );
+            }
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+        }
+    }
+}
+
+/// Delete expired rate limit windows and sessions.
+#[tracing::instrument(name = "Sweeping expired rows", skip_all)]
+pub async fn sweep_expired_rows(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let windows = delete_expired_windows(pool).await?;
+    let sessions = delete_expired_sessions(pool).await?;
+    tracing::info!(windows, sessions, "Deleted expired rows");
+    Ok(())
+}
@@ -1,14 +1,29 @@
+use std::collections::HashMap;
+
+use opentelemetry::global;
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Tracer, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceContextExt, TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+use tokio::task::JoinHandle;
 use tracing::subscriber::set_global_default;
-use tracing::Subscriber;
+use tracing::{Span, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
+use crate::configuration::TelemetrySettings;
+
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Tracer,
 ) -> impl Subscriber + Send + Sync
 where
     // READ: https://doc.rust-lang.org/nomicon/hrtb.html
@@ -23,6 +38,7 @@ where
     // .with provided by tracing_subscriber::layer::SubscriberExt
     Registry::default()
         .with(env_filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
@@ -31,3 +47,138 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger.");
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Spans are exported in batches when an OTLP collector is configured.
+/// Without one they are not exported, but still carry trace ids so that
+/// `traceparent` keeps propagating.
+pub fn tracer_provider(
+    settings: &TelemetrySettings,
+) -> Result<TracerProvider, TraceError> {
+    let config =
+        trace::config().with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]));
+    let mut builder = TracerProvider::builder().with_config(config);
+    if let Some(endpoint) = &settings.otlp_endpoint {
+        let exporter = SpanExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(format!("{}/v1/traces", endpoint))
+                .with_timeout(settings.otlp_timeout()),
+        )
+        .build_span_exporter()?;
+        builder = builder
+            .with_batch_exporter(exporter, opentelemetry::runtime::Tokio);
+    }
+    Ok(builder.build())
+}
+
+/// Installs the W3C propagator and the tracer provider globally, returning
+/// the tracer for `get_subscriber`. Exporting needs a Tokio runtime.
+pub fn init_tracer(settings: &TelemetrySettings) -> Tracer {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider =
+        tracer_provider(settings).expect("Failed to build the OTLP exporter.");
+    let tracer = provider.tracer("zero2prod");
+    global::set_tracer_provider(provider);
+    tracer
+}
+
+/// Writes the current span's trace context into an outgoing request.
+pub fn inject_trace_context(headers: &mut reqwest::header::HeaderMap) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+/// The current span's `traceparent`, for work finished outside the request.
+pub fn current_traceparent() -> Option<String> {
+    let context = Span::current().context();
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut carrier)
+    });
+    carrier.remove("traceparent")
+}
+
+/// Makes `span` part of the trace a `traceparent` was captured from.
+pub fn set_remote_parent(span: &Span, traceparent: &str) {
+    span.set_parent(extract_traceparent(traceparent));
+}
+
+/// Links `span` to the trace a `traceparent` was captured from, for work
+/// done on behalf of several traces at once.
+pub fn add_remote_link(span: &Span, traceparent: &str) {
+    let context = extract_traceparent(traceparent);
+    span.add_link(context.span().span_context().clone());
+}
+
+fn extract_traceparent(traceparent: &str) -> opentelemetry::Context {
+    let carrier =
+        HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+    global::get_text_map_propagator(|propagator| propagator.extract(&carrier))
+}
+
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::tracer_provider;
+    use crate::configuration::TelemetrySettings;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_configured_collector() {
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .and(header("Content-Type", "application/x-protobuf"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+        let provider = tracer_provider(&TelemetrySettings {
+            service_name: "test".into(),
+            otlp_endpoint: Some(collector.uri()),
+            otlp_timeout_ms: 1000,
+        })
+        .unwrap();
+        let subscriber = Registry::default().with(
+            tracing_opentelemetry::layer().with_tracer(provider.tracer("test")),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Exported span").in_scope(|| {});
+        });
+        // Flushing blocks until the batch task has run.
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn no_collector_means_no_exporter() {
+        let provider = tracer_provider(&TelemetrySettings {
+            service_name: "test".into(),
+            otlp_endpoint: None,
+            otlp_timeout_ms: 1000,
+        })
+        .unwrap();
+
+        assert!(provider.force_flush().is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use minijinja::value::Value;
+use minijinja::{
+    context, escape_formatter, AutoEscape, Environment, Output, State,
+    UndefinedBehavior,
+};
+use serde::Serialize;
+
+/// Line width of the plain-text part generated from HTML. Wide enough that
+/// links are never split across lines; mail clients wrap text themselves.
+const TEXT_WIDTH: usize = 10_000;
+
+macro_rules! embedded {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!("../templates/", $name)))),*]
+    };
+}
+
+/// Every template the application renders, as shipped in `templates/`.
+const EMBEDDED: &[(&str, &str)] = embedded![
+    "pages/layout.html",
+    "pages/admin/dashboard.html",
+    "pages/confirmation/confirmed.html",
+    "pages/confirmation/already_confirmed.html",
+    "pages/confirmation/expired.html",
+    "pages/confirmation/invalid.html",
+    "pages/confirmation/resent.html",
+    "pages/unsubscribe/form.html",
+    "pages/unsubscribe/unsubscribed.html",
+    "emails/layout.html",
+    "emails/confirmation.subject.txt",
+    "emails/confirmation.html",
+    "emails/welcome.subject.txt",
+    "emails/welcome.html",
+    "emails/newsletter.subject.txt",
+    "emails/newsletter.html",
+    "emails/newsletter.txt",
+];
+
+/// The emails we send. Each has a `.subject.txt` and a `.html` template
+/// under `emails/`, and optionally a `.txt` one for the plain-text part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplate {
+    Confirmation,
+    Welcome,
+    Newsletter,
+}
+
+impl EmailTemplate {
+    pub const ALL: [EmailTemplate; 3] = [
+        EmailTemplate::Confirmation,
+        EmailTemplate::Welcome,
+        EmailTemplate::Newsletter,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTemplate::Confirmation => "confirmation",
+            EmailTemplate::Welcome => "welcome",
+            EmailTemplate::Newsletter => "newsletter",
+        }
+    }
+
+    fn template(&self, suffix: &str) -> String {
+        format!("emails/{}.{}", self.as_str(), suffix)
+    }
+
+    /// A value for every variable the template is given, so that startup
+    /// can check templates reference nothing else.
+    fn sample_context(&self) -> Value {
+        match self {
+            EmailTemplate::Confirmation => context! {
+                name => "Ursula",
+                confirmation_link => "https://example.com/confirm",
+            },
+            EmailTemplate::Welcome => context! {
+                name => "Ursula",
+                unsubscribe_link => "https://example.com/unsubscribe",
+            },
+            EmailTemplate::Newsletter => context! {
+                title => "Issue #1",
+                html => "<p>Hello</p>",
+                text => "Hello",
+                unsubscribe_link => "https://example.com/unsubscribe",
+            },
+        }
+    }
+}
+
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Templates compiled and checked once at startup. `.html` templates
+/// escape what they interpolate; referencing an undefined variable is an
+/// error rather than an empty string.
+#[derive(Clone)]
+pub struct Templates {
+    env: Arc<Environment<'static>>,
+}
+
+impl Templates {
+    /// The embedded templates, each replaced by the file of the same name
+    /// under `dir` if there is one.
+    pub fn load(dir: Option<&Path>) -> Result<Self, anyhow::Error> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_formatter(html_formatter);
+        for (name, embedded) in EMBEDDED {
+            let source = match dir.map(|dir| dir.join(name)) {
+                Some(path) if path.exists() => read_template(&path)?,
+                _ => (*embedded).to_owned(),
+            };
+            env.add_template_owned(*name, source)
+                .with_context(|| format!("Invalid template {}", name))?;
+        }
+        // Plain-text parts are optional, so not all of them are embedded.
+        for email in EmailTemplate::ALL {
+            let name = email.template("txt");
+            let path = dir.map(|dir| dir.join(&name));
+            if let Some(path) = path.filter(|p| p.exists()) {
+                let source = read_template(&path)?;
+                env.add_template_owned(name.clone(), source)
+                    .with_context(|| format!("Invalid template {}", name))?;
+            }
+        }
+        let templates = Self { env: Arc::new(env) };
+        templates.validate()?;
+        Ok(templates)
+    }
+
+    /// Render everything once, so that a template extending a missing
+    /// layout or using an unknown variable fails startup.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        // Every variable any page is given.
+        let page_context = context! {
+            action => "/subscriptions/unsubscribe?unsubscribe_token=token",
+            username => "admin",
+        };
+        for (name, _) in
+            EMBEDDED.iter().filter(|(n, _)| n.starts_with("pages/"))
+        {
+            self.render(name, &page_context)?;
+        }
+        for email in EmailTemplate::ALL {
+            self.render_email(email, email.sample_context())?;
+        }
+        Ok(())
+    }
+
+    pub fn render(
+        &self,
+        name: &str,
+        context: impl Serialize,
+    ) -> Result<String, anyhow::Error> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .with_context(|| format!("Failed to render template {}", name))
+    }
+
+    /// The plain-text part comes from the email's `.txt` template. Without
+    /// one, or if it renders blank, it is generated from the HTML.
+    pub fn render_email(
+        &self,
+        email: EmailTemplate,
+        context: impl Serialize,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let context = Value::from_serialize(&context);
+        let subject = self.render(&email.template("subject.txt"), &context)?;
+        let html = self.render(&email.template("html"), &context)?;
+        let text_template = email.template("txt");
+        let text = match self.env.get_template(&text_template) {
+            Ok(_) => self.render(&text_template, &context)?,
+            Err(_) => String::new(),
+        };
+        let text = if text.trim().is_empty() {
+            html_to_text(&html)
+        } else {
+            text
+        };
+        Ok(RenderedEmail {
+            subject: subject.trim().to_owned(),
+            html,
+            text,
+        })
+    }
+}
+
+fn read_template(path: &Path) -> Result<String, anyhow::Error> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read template {}", path.display()))
+}
+
+/// minijinja's default formatter, except that `/` is left alone: some mail
+/// clients and link scanners do not decode `&#x2f;` inside `href`.
+fn html_formatter(
+    out: &mut Output,
+    state: &State,
+    value: &Value,
+) -> Result<(), minijinja::Error> {
+    match (state.auto_escape(), value.as_str()) {
+        (AutoEscape::Html, Some(s)) if !value.is_safe() => {
+            let mut escaped = String::with_capacity(s.len());
+            for c in s.chars() {
+                match c {
+                    '&' => escaped.push_str("&amp;"),
+                    '<' => escaped.push_str("&lt;"),
+                    '>' => escaped.push_str("&gt;"),
+                    '"' => escaped.push_str("&quot;"),
+                    '\'' => escaped.push_str("&#x27;"),
+                    c => escaped.push(c),
+                }
+            }
+            Ok(out.write_str(&escaped)?)
+        }
+        _ => escape_formatter(out, state, value),
+    }
+}
+
+pub fn html_to_text(html: &str) -> String {
+    html2text::from_read(html.as_bytes(), TEXT_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates_dir(files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        for (name, source) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn embedded_templates_are_valid() {
+        Templates::load(None).unwrap();
+    }
+
+    #[test]
+    fn email_variables_are_html_escaped() {
+        let templates = Templates::load(None).unwrap();
+
+        let email = templates
+            .render_email(
+                EmailTemplate::Confirmation,
+                context! {
+                    name => "<b>Ursula</b>",
+                    confirmation_link => "https://example.com/confirm",
+                },
+            )
+            .unwrap();
+
+        assert!(email.html.contains("Hi &lt;b&gt;Ursula&lt;/b&gt;"));
+        assert!(email.text.contains("Hi <b>Ursula</b>"));
+    }
+
+    #[test]
+    fn the_text_part_is_generated_from_html_without_a_text_template() {
+        let templates = Templates::load(None).unwrap();
+
+        let email = templates
+            .render_email(
+                EmailTemplate::Confirmation,
+                EmailTemplate::Confirmation.sample_context(),
+            )
+            .unwrap();
+
+        assert_eq!(email.subject, "Please confirm your subscription");
+        assert!(!email.text.contains('<'));
+        assert!(email.text.contains("https://example.com/confirm"));
+    }
+
+    #[test]
+    fn a_blank_text_template_falls_back_to_the_html() {
+        let templates = Templates::load(None).unwrap();
+
+        let email = templates
+            .render_email(
+                EmailTemplate::Newsletter,
+                context! {
+                    title => "Issue #1",
+                    html => "<p>Hello from the HTML</p>",
+                    text => (),
+                    unsubscribe_link => "https://example.com/unsubscribe",
+                },
+            )
+            .unwrap();
+
+        assert!(email.text.contains("Hello from the HTML"));
+        assert!(email.text.contains("https://example.com/unsubscribe"));
+    }
+
+    #[test]
+    fn a_file_in_the_directory_replaces_the_embedded_template() {
+        let dir = templates_dir(&[
+            ("emails/welcome.subject.txt", "Hello {{ name }} & co"),
+            ("emails/welcome.txt", "Welcome aboard, {{ name }}!"),
+        ]);
+
+        let templates = Templates::load(Some(&dir)).unwrap();
+        let email = templates
+            .render_email(
+                EmailTemplate::Welcome,
+                EmailTemplate::Welcome.sample_context(),
+            )
+            .unwrap();
+
+        assert_eq!(email.subject, "Hello Ursula & co");
+        assert_eq!(email.text, "Welcome aboard, Ursula!");
+        assert!(email.html.contains("Your subscription is confirmed."));
+    }
+
+    #[test]
+    fn a_broken_template_fails_to_load() {
+        let dir = templates_dir(&[("pages/layout.html", "{% block %}")]);
+
+        assert!(Templates::load(Some(&dir)).is_err());
+    }
+
+    #[test]
+    fn a_template_using_an_unknown_variable_fails_to_load() {
+        let dir =
+            templates_dir(&[("emails/confirmation.html", "{{ confirm_url }}")]);
+
+        assert!(Templates::load(Some(&dir)).is_err());
+    }
+
+    #[test]
+    fn a_template_extending_a_missing_layout_fails_to_load() {
+        let dir = templates_dir(&[(
+            "pages/confirmation/invalid.html",
+            r#"{% extends "pages/base.html" %}"#,
+        )]);
+
+        assert!(Templates::load(Some(&dir)).is_err());
+    }
+}
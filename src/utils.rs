@@ -0,0 +1,22 @@
+use actix_web::error::InternalError;
+use actix_web::http::header::LOCATION;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+
+use crate::errors::ErrorEnvelope;
+
+/// Return an opaque 500 while preserving the error root cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    let response = ErrorEnvelope::internal()
+        .into_response(StatusCode::INTERNAL_SERVER_ERROR);
+    InternalError::from_response(e, response).into()
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
@@ -0,0 +1,176 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn subscribing_with_a_bundled_disposable_domain_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40mailinator.com".into(),
+        )
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["details"][0]["field"], "email");
+    assert_eq!(body["details"][0]["code"], "blocked_domain");
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+    assert!(app
+        .get_metrics()
+        .await
+        .contains("subscriptions_blocked_total 1"));
+}
+
+#[tokio::test]
+async fn subdomains_of_a_blocked_domain_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40eu.Mailinator.com".into(),
+        )
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn domains_can_be_blocked_and_allowed_through_configuration() {
+    let app = spawn_app_with(|c| {
+        c.application.domain_filter.blocked_domains =
+            vec!["example.org".into()];
+        c.application.domain_filter.allowed_domains =
+            vec!["mailinator.com".into()];
+    })
+    .await;
+
+    let blocked = app
+        .post_subscriptions("name=le%20guin&email=ursula%40example.org".into())
+        .await;
+    let allowed = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40mailinator.com".into(),
+        )
+        .await;
+
+    assert_eq!(400, blocked.status().as_u16());
+    assert_eq!(200, allowed.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_domain_blocked_by_an_admin_is_rejected_straight_away() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_email_domain_rule(serde_json::json!({
+            "domain": "Example.ORG",
+            "rule": "block",
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40example.org".into())
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    let rules: serde_json::Value =
+        app.get_email_domain_rules().await.json().await.unwrap();
+    assert_eq!(rules[0]["domain"], "example.org");
+    assert_eq!(rules[0]["rule"], "block");
+}
+
+#[tokio::test]
+async fn an_admin_can_allow_a_bundled_domain_and_take_it_back() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.post_email_domain_rule(serde_json::json!({
+        "domain": "mailinator.com",
+        "rule": "allow",
+    }))
+    .await;
+    let allowed = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula%40mailinator.com".into(),
+        )
+        .await;
+    assert_eq!(200, allowed.status().as_u16());
+
+    let response = app.delete_email_domain_rule("mailinator.com").await;
+    assert_eq!(204, response.status().as_u16());
+    let blocked = app
+        .post_subscriptions(
+            "name=le%20guin&email=other%40mailinator.com".into(),
+        )
+        .await;
+    assert_eq!(400, blocked.status().as_u16());
+}
+
+#[tokio::test]
+async fn rules_written_elsewhere_are_picked_up_on_reload() {
+    let app = spawn_app_with(|c| {
+        c.application.domain_filter.reload_interval_secs = 1;
+    })
+    .await;
+
+    // As if another instance had saved the rule.
+    sqlx::query!(
+        "INSERT INTO email_domain_rules (domain, rule) VALUES ('example.org', 'block')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40example.org".into())
+        .await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_domains_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_email_domain_rule(serde_json::json!({
+            "domain": "not a domain",
+            "rule": "block",
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["details"][0]["field"], "domain");
+}
+
+#[tokio::test]
+async fn deleting_a_missing_rule_returns_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.delete_email_domain_rule("example.org").await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_email_domains() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_domain_rule(serde_json::json!({
+            "domain": "example.org",
+            "rule": "block",
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,169 @@
+use serde_json::json;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+fn subscribe_body() -> String {
+    format!("name=le%20guin&email={}", EMAIL)
+}
+
+async fn subscriber_count(app: &crate::helpers::TestApp) -> i64 {
+    sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn a_suppressed_address_cannot_subscribe() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_suppression(json!({ "email": EMAIL, "reason": "legal_request" }))
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(subscribe_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Answered as any other subscription, so the list cannot be probed.
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_lifted_suppression_lets_the_address_subscribe_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_unconfirmed_subscriber(EMAIL).await;
+    app.post_postmark_webhook(&json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+        "Email": EMAIL,
+    }))
+    .await;
+
+    app.post_subscriptions(subscribe_body()).await;
+    assert_eq!(app.get_subscriber_status(EMAIL).await, "bounced");
+
+    let response = app.delete_suppression(EMAIL).await;
+    assert_eq!(204, response.status().as_u16());
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(subscribe_body()).await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        app.get_subscriber_status(EMAIL).await,
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_suppressed_by_an_admin_get_no_newsletter() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber(EMAIL).await;
+    app.post_suppression(json!({ "email": EMAIL, "reason": "manual" }))
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(json!({
+        "title": "Newsletter title",
+        "content": { "html": "<p>Newsletter body as HTML</p>" },
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let status = sqlx::query!(
+        "SELECT status FROM email_messages WHERE template = 'newsletter'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status;
+    assert_eq!(status, "suppressed");
+}
+
+#[tokio::test]
+async fn suppressions_are_listed_by_canonical_address_with_their_reason() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_suppression(json!({
+            "email": "ursula@Example.ORG",
+            "reason": "manual",
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let response = app
+        .post_suppression(json!({
+            "email": "ursula@example.org",
+            "reason": "legal_request",
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let suppressions: serde_json::Value =
+        app.get_suppressions().await.json().await.unwrap();
+    assert_eq!(suppressions.as_array().unwrap().len(), 1);
+    assert_eq!(suppressions[0]["email"], "ursula@example.org");
+    assert_eq!(suppressions[0]["reason"], "legal_request");
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let invalid_email = app
+        .post_suppression(
+            json!({ "email": "not an email", "reason": "manual" }),
+        )
+        .await;
+    let invalid_reason = app
+        .post_suppression(json!({ "email": EMAIL, "reason": "dislike" }))
+        .await;
+
+    assert_eq!(400, invalid_email.status().as_u16());
+    let body: serde_json::Value = invalid_email.json().await.unwrap();
+    assert_eq!(body["details"][0]["field"], "email");
+    assert_eq!(400, invalid_reason.status().as_u16());
+}
+
+#[tokio::test]
+async fn lifting_a_missing_suppression_returns_404() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app.delete_suppression(EMAIL).await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_suppressions() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_suppression(json!({ "email": EMAIL, "reason": "manual" }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,253 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn a_failed_delivery_is_rescheduled_with_backoff() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() AS "in_the_future!"
+        FROM delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The failed delivery should still be queued");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.in_the_future);
+}
+
+#[tokio::test]
+async fn a_rescheduled_delivery_is_sent_once_due() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("UPDATE delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let queued =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(queued, 0);
+}
+
+#[tokio::test]
+async fn deliveries_are_dropped_after_the_maximum_number_of_attempts() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+    )
+    .await;
+    sqlx::query!(
+        "UPDATE delivery_queue SET n_retries = 4, execute_after = now()"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let queued =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(queued, 0);
+}
+
+#[tokio::test]
+async fn a_sent_email_is_recorded_with_the_provider_message_id() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!({
+                "ErrorCode": 0,
+                "Message": "OK",
+                "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            }),
+        ))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+    )
+    .await;
+    let queued = sqlx::query!("SELECT status FROM email_messages")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.status, "queued");
+    app.dispatch_all_pending_emails().await;
+
+    let message = sqlx::query!(
+        r#"SELECT m.recipient_email, m.template, m.status,
+            m.provider_message_id, m.subscriber_id = s.id AS "matches!"
+        FROM email_messages m, subscriptions s"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(message.recipient_email, "test_user@gmail.com");
+    assert_eq!(message.template, "confirmation");
+    assert_eq!(message.status, "sent");
+    assert_eq!(
+        message.provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+    assert!(message.matches);
+}
+
+#[tokio::test]
+async fn an_email_given_up_on_is_recorded_as_failed() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+    )
+    .await;
+    sqlx::query!(
+        "UPDATE delivery_queue SET n_retries = 4, execute_after = now()"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let message =
+        sqlx::query!("SELECT status, provider_message_id FROM email_messages")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(message.status, "failed");
+    assert_eq!(message.provider_message_id, None);
+}
+
+#[tokio::test]
+async fn due_emails_are_sent_in_one_batch_with_outcomes_per_recipient() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK", "MessageID": "first" },
+                {
+                    "ErrorCode": 406,
+                    "Message": "You tried to send to an inactive recipient."
+                },
+                { "ErrorCode": 0, "Message": "OK", "MessageID": "third" },
+            ]),
+        ))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Queued in one statement, so that whoever claims them gets all three.
+    sqlx::query!(
+        r#"
+        WITH queued AS (
+            SELECT gen_random_uuid() AS id, recipient_email, execute_after
+            FROM (VALUES
+                ('first@gmail.com', now() - interval '3 seconds'),
+                ('second@gmail.com', now() - interval '2 seconds'),
+                ('third@gmail.com', now() - interval '1 second')
+            ) AS v (recipient_email, execute_after)
+        ), message AS (
+            INSERT INTO email_messages (id, recipient_email, template, status)
+            SELECT id, recipient_email, 'newsletter', 'queued' FROM queued
+        )
+        INSERT INTO delivery_queue
+            (id, recipient_email, subject, html_body, text_body,
+            execute_after)
+        SELECT id, recipient_email, 'Issue', '<p>Issue</p>', 'Issue',
+            execute_after
+        FROM queued
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let messages = sqlx::query!(
+        r#"SELECT recipient_email, status, provider_message_id
+        FROM email_messages ORDER BY recipient_email"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let outcomes: Vec<_> = messages
+        .iter()
+        .map(|m| (m.status.as_str(), m.provider_message_id.as_deref()))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            ("sent", Some("first")),
+            ("failed", None),
+            ("sent", Some("third")),
+        ]
+    );
+    let queued =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(queued, 0);
+}
@@ -0,0 +1,105 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use zero2prod::configuration::get_configuration;
+use zero2prod::startup::Application;
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn email_body(request: &wiremock::Request) -> serde_json::Value {
+    serde_json::from_slice(&request.body).unwrap()
+}
+
+async fn last_email(app: &TestApp) -> serde_json::Value {
+    let requests = app.email_server.received_requests().await.unwrap();
+    email_body(requests.last().unwrap())
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_rendered_from_its_template() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=Tom%20%26%20Jerry&email=ursula%40gmail.com".into(),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let body = last_email(&app).await;
+    assert_eq!(body["Subject"], "Please confirm your subscription");
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("Hi Tom &amp; Jerry,"));
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.contains("Hi Tom & Jerry,"));
+    assert!(!text.contains("<p>"));
+}
+
+#[tokio::test]
+async fn confirming_sends_a_welcome_email_with_an_unsubscribe_link() {
+    let app = spawn_app().await;
+
+    app.create_confirmed_subscriber("ursula@gmail.com").await;
+
+    let token = app.get_unsubscribe_token("ursula@gmail.com").await;
+    let body = last_email(&app).await;
+    assert_eq!(body["Subject"], "Welcome to our newsletter");
+    assert_eq!(body["To"], "ursula@gmail.com");
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&token));
+    assert!(body["TextBody"].as_str().unwrap().contains(&token));
+}
+
+#[tokio::test]
+async fn a_newsletter_without_a_text_part_gets_one_generated_from_its_html() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber("ursula@gmail.com").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Issue <1>",
+            "content": {
+                "html": "<h1>Hello</h1><p>Newsletter body as <b>HTML</b></p>",
+            }
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body = last_email(&app).await;
+    assert_eq!(body["Subject"], "Issue <1>");
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<p>Newsletter body as <b>HTML</b></p>"));
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.contains("Newsletter body as"));
+    assert!(!text.contains("<p>"));
+    assert!(text.contains("/subscriptions/unsubscribe/one-click?"));
+}
+
+#[tokio::test]
+async fn a_broken_template_fails_the_application_build() {
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(dir.join("emails")).unwrap();
+    std::fs::write(
+        dir.join("emails/welcome.html"),
+        r#"{% extends "emails/missing.html" %}"#,
+    )
+    .unwrap();
+    let mut config = get_configuration().unwrap();
+    config.application.port = 0;
+    config.metrics.port = 0;
+    config.application.templates_dir = Some(dir);
+
+    let application = Application::build(config).await;
+
+    assert!(application.is_err());
+}
@@ -1,4 +1,9 @@
-use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::{get_configuration, ReadinessCheck};
+use zero2prod::startup::Application;
+
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -16,3 +21,125 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn liveness_returns_200() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn readiness_reports_the_database_as_up() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["dependencies"]["database"]["status"], "up");
+    assert!(body["dependencies"]["database"]["latency_ms"].is_u64());
+    assert!(body["dependencies"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn readiness_returns_503_when_the_database_is_unreachable() {
+    let mut config =
+        get_configuration().expect("Failed to read configuration.");
+    config.application.port = 0;
+    config.metrics.port = 0;
+    // Nothing listens on port 1; the pool is lazy, so the app still starts.
+    config.database.port = 1;
+    let application = Application::build(config)
+        .await
+        .expect("Failed to build application");
+    let address = format!("http://127.0.0.1:{}", application.port());
+    #[allow(clippy::let_underscore_future)]
+    let _ = tokio::spawn(application.run_until_stopped());
+
+    let response = reqwest::get(format!("{}/health/ready", address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["dependencies"]["database"]["status"], "down");
+    assert!(body["dependencies"]["database"]["error"].is_string());
+}
+
+#[tokio::test]
+async fn readiness_returns_503_when_a_required_email_provider_is_down() {
+    let app = spawn_app_with(|c| {
+        c.email_client.readiness_check = ReadinessCheck::Required;
+    })
+    .await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(503, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["dependencies"]["database"]["status"], "up");
+    assert_eq!(body["dependencies"]["email_provider"]["status"], "down");
+}
+
+#[tokio::test]
+async fn readiness_ignores_an_optional_email_provider_being_down() {
+    let app = spawn_app_with(|c| {
+        c.email_client.readiness_check = ReadinessCheck::Optional;
+    })
+    .await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["dependencies"]["email_provider"]["status"], "down");
+    assert_eq!(body["dependencies"]["email_provider"]["required"], false);
+}
+
+#[tokio::test]
+async fn readiness_probes_a_required_email_provider() {
+    let app = spawn_app_with(|c| {
+        c.email_client.readiness_check = ReadinessCheck::Required;
+    })
+    .await;
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["dependencies"]["email_provider"]["status"], "up");
+}
@@ -1,20 +1,40 @@
+use argon2::password_hash::SaltString;
+use std::sync::Arc;
+
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use secrecy::ExposeSecret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, EmailProvider, Settings,
+    TelemetrySettings, WebhookSettings,
+};
+use zero2prod::delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::domain::EmailNormalization;
+use zero2prod::email_client::{EmailClient, SuppressingEmailClient};
+use zero2prod::rate_limit::Quota;
 use zero2prod::startup::{get_connection_pool, Application};
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::telemetry::{get_subscriber, init_subscriber, init_tracer};
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    // Spans carry trace ids for propagation but are never exported.
+    let tracer = init_tracer(&TelemetrySettings {
+        service_name: subscriber_name.clone(),
+        otlp_endpoint: None,
+        otlp_timeout_ms: 0,
+    });
     if std::env::var("TEST_LOG").is_ok() {
         let subscriber = get_subscriber(
             subscriber_name,
             default_filter_level,
             std::io::stdout,
+            tracer,
         );
         init_subscriber(subscriber);
     } else {
@@ -22,6 +42,7 @@ static TRACING: Lazy<()> = Lazy::new(|| {
             subscriber_name,
             default_filter_level,
             std::io::sink,
+            tracer,
         );
         init_subscriber(subscriber);
     };
@@ -30,8 +51,60 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 pub struct TestApp {
     pub address: String,
     pub port: u16,
+    pub metrics_address: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+    pub webhooks: WebhookSettings,
+    pub email_normalization: EmailNormalization,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub async fn login(&self, app: &TestApp) {
+        app.post_login(&serde_json::json!({
+            "username": &self.username,
+            "password": &self.password,
+        }))
+        .await;
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash)
+            VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -40,8 +113,115 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Deliver everything in the delivery queue that is due. The background
+    /// worker may be holding a row, so wait until the queue is truly drained.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            let outcome = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                self.email_normalization,
+            )
+            .await
+            .unwrap();
+            if let ExecutionOutcome::EmptyQueue = outcome {
+                let due = sqlx::query!(
+                    r#"SELECT count(*) AS "count!" FROM delivery_queue
+                    WHERE execute_after <= now()"#
+                )
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap()
+                .count;
+                if due == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    pub async fn get_metrics(&self) -> String {
+        reqwest::get(format!("{}/metrics", &self.metrics_address))
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
-        reqwest::Client::new()
+        self.api_client
             .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
@@ -50,6 +230,230 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_resend_confirmation(
+        &self,
+        body: String,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/confirm/resend", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_postmark_webhook(
+        &self,
+        event: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.webhooks.username,
+                Some(self.webhooks.password.expose_secret()),
+            )
+            .json(event)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_email_domain_rules(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/email-domains", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_email_domain_rule(
+        &self,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/email-domains", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_email_domain_rule(
+        &self,
+        domain: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/email-domains/{}", &self.address, domain))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_suppressions(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/suppressions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_suppression(
+        &self,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/suppressions", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_suppression(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/suppressions/{}", &self.address, email))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters(
+        &self,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Subscribe `email` without confirming it.
+    pub async fn create_unconfirmed_subscriber(&self, email: &str) {
+        let body = serde_urlencoded::to_string([
+            ("name", "BoatyMcBoatFace"),
+            ("email", email),
+        ])
+        .unwrap();
+
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+        self.dispatch_all_pending_emails().await;
+    }
+
+    pub async fn get_unsubscribe_form(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+                &self.address, token
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+                &self.address, token
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// POST as a mailbox provider would: no cookies, a fixed form body.
+    pub async fn post_one_click_unsubscribe(
+        &self,
+        url: reqwest::Url,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_unsubscribe_token(&self, email: &str) -> String {
+        sqlx::query!(
+            "SELECT unsubscribe_token FROM subscriptions WHERE email = $1",
+            email
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch unsubscribe token")
+        .unsubscribe_token
+    }
+
+    pub async fn get_subscriber_status(&self, email: &str) -> String {
+        sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("Failed to fetch subscriber status")
+            .status
+    }
+
+    /// Subscribe `email`, follow the confirmation link and deliver the
+    /// welcome email. Leaves the mock email server with no mounted mocks.
+    pub async fn create_confirmed_subscriber(&self, email: &str) {
+        let body = serde_urlencoded::to_string([
+            ("name", "BoatyMcBoatFace"),
+            ("email", email),
+        ])
+        .unwrap();
+
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create confirmed subscriber")
+            .expect(2)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+        self.dispatch_all_pending_emails().await;
+
+        let email_request = &self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let confirmation_links = self.get_confirmation_links(email_request);
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        self.dispatch_all_pending_emails().await;
+    }
+
+    pub async fn expire_subscription_tokens(&self) {
+        sqlx::query!(
+            "UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'",
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to expire subscription tokens");
+    }
+
     pub fn get_confirmation_links(
         &self,
         email_request: &wiremock::Request,
@@ -71,13 +475,40 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
+
+    /// The URL in the `List-Unsubscribe` header sent to Postmark.
+    pub fn get_list_unsubscribe_url(
+        &self,
+        email_request: &wiremock::Request,
+    ) -> reqwest::Url {
+        let body: serde_json::Value =
+            serde_json::from_slice(&email_request.body).unwrap();
+        let value = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|header| header["Name"] == "List-Unsubscribe")
+            .expect("No List-Unsubscribe header")["Value"]
+            .as_str()
+            .unwrap();
+        let raw_link = value.trim_start_matches('<').trim_end_matches('>');
+        let mut link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the app after applying `customise` to the test configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     // mock email server
@@ -87,7 +518,21 @@ pub async fn spawn_app() -> TestApp {
         let mut c = get_configuration().expect("Failed to read configuration.");
         c.database.database_name = Uuid::new_v4().to_string();
         c.application.port = 0;
+        c.metrics.port = 0;
+        c.email_client.provider = EmailProvider::Postmark;
         c.email_client.base_url = email_server.uri();
+        // Failed deliveries are retried by the delivery worker; tests
+        // drive those retries explicitly.
+        c.email_client.retry.max_attempts = 1;
+        // Tests exercising the limits lower them again.
+        let unlimited = Quota {
+            max_requests: u32::MAX,
+            window_secs: 3600,
+        };
+        c.application.rate_limit.subscribe_per_ip = unlimited;
+        c.application.rate_limit.subscribe_per_email = unlimited;
+        c.application.rate_limit.confirm_per_ip = unlimited;
+        customise(&mut c);
         c
     };
 
@@ -98,14 +543,37 @@ pub async fn spawn_app() -> TestApp {
 
     let application_port = application.port();
     let address = format!("http://127.0.0.1:{}", application.port());
+    let metrics_address =
+        format!("http://127.0.0.1:{}", application.metrics_port());
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+
+    let db_pool = get_connection_pool(&config.database);
+    // Wrapped as in the application, so the suppression list applies.
+    let email_client = Arc::new(SuppressingEmailClient::new(
+        config.email_client.clone().client(),
+        db_pool.clone(),
+    ));
+    let test_app = TestApp {
         address,
-        db_pool: get_connection_pool(&config.database),
+        metrics_address,
+        db_pool,
         email_server,
         port: application_port,
-    }
+        email_client,
+        test_user: TestUser::generate(),
+        api_client,
+        webhooks: config.application.webhooks.clone(),
+        email_normalization: config.application.email_normalization,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 pub async fn configure_db(config: &DatabaseSettings) -> PgPool {
@@ -132,3 +600,8 @@ pub async fn configure_db(config: &DatabaseSettings) -> PgPool {
 
     db_pool
 }
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
@@ -0,0 +1,119 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with};
+use secrecy::Secret;
+use zero2prod::authentication::create_user;
+use zero2prod::session_store::SessionStoreKind;
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    // The flash message is gone on reload
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("<p><i>Authentication failed</i></p>"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn migrations_do_not_seed_any_user() {
+    let app = spawn_app().await;
+
+    let others = sqlx::query!(
+        r#"SELECT count(*) AS "count!" FROM users WHERE user_id != $1"#,
+        app.test_user.user_id,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+
+    assert_eq!(others, 0);
+}
+
+#[tokio::test]
+async fn a_created_admin_can_log_in() {
+    let app = spawn_app().await;
+    create_user(
+        "first-admin",
+        Secret::new("a long passphrase".into()),
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let login_body = serde_json::json!({
+        "username": "first-admin",
+        "password": "a long passphrase"
+    });
+    let response = app.post_login(&login_body).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_dashboard_escapes_the_username() {
+    let app = spawn_app().await;
+    create_user(
+        "<b>admin</b>",
+        Secret::new("a long passphrase".into()),
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+    app.post_login(&serde_json::json!({
+        "username": "<b>admin</b>",
+        "password": "a long passphrase"
+    }))
+    .await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+
+    assert!(html_page.contains("Welcome &lt;b&gt;admin&lt;/b&gt;!"));
+    assert!(!html_page.contains("<b>admin</b>"));
+}
+
+#[tokio::test]
+async fn sessions_can_be_backed_by_postgres() {
+    let app = spawn_app_with(|c| {
+        c.application.session_store = SessionStoreKind::Postgres;
+    })
+    .await;
+
+    app.test_user.login(&app).await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    let sessions = sqlx::query!(r#"SELECT count(*) AS "count!" FROM sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(sessions, 1);
+
+    app.post_logout().await;
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -1,4 +1,17 @@
+mod admin_dashboard;
+mod admin_email_domains;
+mod admin_suppressions;
+mod change_password;
+mod delivery_worker;
+mod email_templates;
 mod health_check;
 mod helpers;
+mod login;
+mod metrics;
+mod newsletters;
+mod rate_limits;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod telemetry;
+mod webhooks;
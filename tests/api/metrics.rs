@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with};
+use zero2prod::rate_limit::Quota;
+
+#[tokio::test]
+async fn metrics_are_not_served_on_the_application_listener() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn http_requests_are_counted_per_route_and_status() {
+    let app = spawn_app().await;
+
+    reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .unwrap();
+    app.post_subscriptions("name=le%20guin".into()).await;
+
+    let metrics = app.get_metrics().await;
+    assert!(metrics.contains(
+        r#"http_requests_total{method="GET",route="/health/live",status="200"} 1"#
+    ));
+    assert!(metrics.contains(
+        r#"http_requests_total{method="POST",route="/subscriptions",status="400"} 1"#
+    ));
+    assert!(metrics.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/health/live",status="200"} 1"#
+    ));
+}
+
+#[tokio::test]
+async fn requests_refused_by_a_middleware_are_counted() {
+    let app = spawn_app_with(|c| {
+        c.application.rate_limit.subscribe_per_ip = Quota {
+            max_requests: 0,
+            window_secs: 3600,
+        };
+    })
+    .await;
+
+    let throttled = app.post_subscriptions("name=le%20guin".into()).await;
+    let anonymous = app.get_admin_dashboard().await;
+
+    assert_eq!(429, throttled.status().as_u16());
+    assert_eq!(303, anonymous.status().as_u16());
+    let metrics = app.get_metrics().await;
+    assert!(metrics.contains(
+        r#"http_requests_total{method="POST",route="/subscriptions",status="429"} 1"#
+    ));
+    assert!(metrics.contains(
+        r#"http_requests_total{method="GET",route="/admin/dashboard",status="303"} 1"#
+    ));
+}
+
+#[tokio::test]
+async fn pool_gauges_are_exported() {
+    let app = spawn_app().await;
+
+    let metrics = app.get_metrics().await;
+
+    assert!(metrics.contains("db_pool_connections "));
+    assert!(metrics.contains("db_pool_idle_connections "));
+    assert!(metrics.contains("db_pool_acquire_wait_seconds "));
+}
+
+#[tokio::test]
+async fn subscriptions_created_and_confirmed_are_counted() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let metrics = app.get_metrics().await;
+    assert!(metrics.contains("subscriptions_created_total 1"));
+    assert!(metrics.contains("subscriptions_confirmed_total 1"));
+}
+
+#[tokio::test]
+async fn email_sends_are_counted_per_provider_and_status_class() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+
+    // Left to the background worker, which is the metered sender.
+    let expected =
+        r#"email_sends_total{provider="postmark",status_class="4xx"} 1"#;
+    for _ in 0..50 {
+        if app.get_metrics().await.contains(expected) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The failed send was never counted.");
+}
@@ -0,0 +1,220 @@
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_unconfirmed_subscriber("test_user@gmail.com")
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_include_the_subscribers_unsubscribe_link() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    // The signed one-click URL, not the plaintext token.
+    let url = app.get_list_unsubscribe_url(&email_request);
+    let (_, signature) = url
+        .query_pairs()
+        .find(|(key, _)| key == "signature")
+        .unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(html.contains(signature.as_ref()));
+    assert!(text.contains(signature.as_ref()));
+    assert!(!html.contains(&token));
+    assert!(!text.contains(&token));
+}
+
+#[tokio::test]
+async fn newsletters_carry_one_click_list_unsubscribe_headers() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.contains(&serde_json::json!({
+        "Name": "List-Unsubscribe-Post",
+        "Value": "List-Unsubscribe=One-Click",
+    })));
+    let url = app.get_list_unsubscribe_url(&email_request);
+    assert_eq!(url.path(), "/subscriptions/unsubscribe/one-click");
+    assert!(url.query_pairs().any(|(key, _)| key == "signature"));
+}
+
+#[tokio::test]
+async fn subscribers_with_invalid_stored_emails_are_skipped() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+    sqlx::query!(
+        r#"INSERT INTO subscriptions
+            (id, email, email_canonical, name, subscribed_at, status,
+            unsubscribe_token)
+        VALUES ($1, 'not-an-email', 'not-an-email', 'Broken', $2, 'confirmed',
+            'broken')"#,
+        Uuid::new_v4(),
+        Utc::now(),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletters(invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn invalid_newsletter_bodies_are_rejected_with_the_error_envelope() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({"title": "Newsletter!"}))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_request");
+    assert!(body["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,202 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::Settings;
+use zero2prod::rate_limit::{Quota, RateLimitBackendKind};
+use zero2prod::sweeper::sweep_expired_rows;
+
+fn quota(max_requests: u32) -> Quota {
+    Quota {
+        max_requests,
+        window_secs: 3600,
+    }
+}
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn assert_throttled(response: reqwest::Response) {
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=3600).contains(&retry_after));
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "rate_limited");
+    assert!(body["request_id"].is_string());
+}
+
+#[tokio::test]
+async fn subscribe_is_limited_per_ip() {
+    for backend in
+        [RateLimitBackendKind::Memory, RateLimitBackendKind::Postgres]
+    {
+        let app = spawn_app_with(|c: &mut Settings| {
+            c.application.rate_limit.backend = backend;
+            c.application.rate_limit.subscribe_per_ip = quota(2);
+        })
+        .await;
+        mount_email_server(&app).await;
+
+        for i in 0..2 {
+            let body = format!("name=le%20guin&email=ursula{}%40gmail.com", i);
+            let response = app.post_subscriptions(body).await;
+            assert_eq!(200, response.status().as_u16());
+        }
+        let response = app
+            .post_subscriptions("name=le%20guin&email=other%40gmail.com".into())
+            .await;
+
+        assert_throttled(response).await;
+    }
+}
+
+#[tokio::test]
+async fn a_spoofed_forwarded_for_header_does_not_reset_the_limit() {
+    let app = spawn_app_with(|c| {
+        c.application.rate_limit.client_ip_header =
+            Some("do-connecting-ip".into());
+        c.application.rate_limit.subscribe_per_ip = quota(2);
+    })
+    .await;
+    mount_email_server(&app).await;
+    let subscribe = |i: u32| {
+        app.api_client
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("203.0.113.{}, 198.51.100.7", i))
+            .header("Do-Connecting-Ip", "198.51.100.7")
+            .body(format!("name=le%20guin&email=ursula{}%40gmail.com", i))
+            .send()
+    };
+
+    for i in 0..2 {
+        let response = subscribe(i).await.unwrap();
+        assert_eq!(200, response.status().as_u16());
+    }
+    let response = subscribe(2).await.unwrap();
+
+    assert_throttled(response).await;
+}
+
+#[tokio::test]
+async fn subscribe_is_limited_per_target_email() {
+    let app = spawn_app_with(|c| {
+        c.application.rate_limit.subscribe_per_email = quota(1);
+    })
+    .await;
+    mount_email_server(&app).await;
+
+    let first = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+    // Same canonical address, differently spelled.
+    let second = app
+        .post_subscriptions("name=le%20guin&email=ursula%40GMAIL.com".into())
+        .await;
+    let other = app
+        .post_subscriptions("name=le%20guin&email=other%40gmail.com".into())
+        .await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_throttled(second).await;
+    assert_eq!(200, other.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_throttled_subscribe_does_not_queue_an_email() {
+    let app = spawn_app_with(|c| {
+        c.application.rate_limit.subscribe_per_email = quota(1);
+    })
+    .await;
+
+    for _ in 0..3 {
+        app.post_subscriptions(
+            "name=le%20guin&email=ursula%40gmail.com".into(),
+        )
+        .await;
+    }
+
+    let queued =
+        sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.count, 1);
+}
+
+#[tokio::test]
+async fn confirm_is_limited_per_ip() {
+    let app = spawn_app_with(|c| {
+        c.application.rate_limit.confirm_per_ip = quota(3);
+    })
+    .await;
+
+    for _ in 0..3 {
+        let response = reqwest::get(&format!(
+            "{}/subscriptions/confirm?subscription_token=guess",
+            app.address
+        ))
+        .await
+        .unwrap();
+        assert_eq!(401, response.status().as_u16());
+    }
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=guess",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_throttled(response).await;
+}
+
+#[tokio::test]
+async fn the_sweep_deletes_expired_windows_and_sessions_only() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"INSERT INTO rate_limits (key, hits, window_ends_at) VALUES
+            ('expired', 1, now() - interval '1 minute'),
+            ('live', 1, now() + interval '1 minute')"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"INSERT INTO sessions (session_key, state, expires_at) VALUES
+            ('expired', '{}', now() - interval '1 minute'),
+            ('live', '{}', now() + interval '1 minute')"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    sweep_expired_rows(&app.db_pool).await.unwrap();
+
+    let windows = sqlx::query!("SELECT key FROM rate_limits")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let sessions = sqlx::query!("SELECT session_key FROM sessions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        windows.into_iter().map(|r| r.key).collect::<Vec<_>>(),
+        ["live"]
+    );
+    assert_eq!(
+        sessions
+            .into_iter()
+            .map(|r| r.session_key)
+            .collect::<Vec<_>>(),
+        ["live"]
+    );
+}
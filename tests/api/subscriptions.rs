@@ -1,7 +1,10 @@
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use zero2prod::canonical_emails::backfill_canonical_emails;
+use zero2prod::routes::hash_subscription_token;
+
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -41,6 +44,147 @@ async fn subscribe_persists_user_data() {
     assert_eq!(saved.status, "pending_confirmation");
 }
 
+#[tokio::test]
+async fn addresses_differing_only_by_domain_case_are_one_subscriber() {
+    let app = spawn_app().await;
+
+    app.post_subscriptions("name=le%20guin&email=Ursula%40Example.COM".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=Ursula%40example.com".into())
+        .await;
+
+    let saved =
+        sqlx::query!(r#"SELECT email, email_canonical AS "email_canonical!" FROM subscriptions"#)
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula@Example.COM");
+    assert_eq!(saved[0].email_canonical, "Ursula@example.com");
+}
+
+#[tokio::test]
+async fn idn_domains_are_stored_in_punycode_and_sent_as_typed() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=le%20guin&email=%20ursula%40b%C3%BCcher.example%20".into(),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved =
+        sqlx::query!(r#"SELECT email, email_canonical AS "email_canonical!" FROM subscriptions"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.email, "ursula@bücher.example");
+    assert_eq!(saved.email_canonical, "ursula@xn--bcher-kva.example");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula@bücher.example");
+}
+
+#[tokio::test]
+async fn the_local_part_is_case_insensitive_when_configured() {
+    let app = spawn_app_with(|c| {
+        c.application
+            .email_normalization
+            .case_insensitive_local_part = true;
+    })
+    .await;
+
+    app.post_subscriptions("name=le%20guin&email=Ursula%40example.com".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=URSULA%40example.com".into())
+        .await;
+
+    let saved = sqlx::query!(
+        r#"SELECT email_canonical AS "email_canonical!" FROM subscriptions"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email_canonical, "ursula@example.com");
+}
+
+#[tokio::test]
+async fn the_backfill_canonicalizes_old_rows_like_new_subscriptions() {
+    let app = spawn_app().await;
+    // Stored before `email_canonical` existed: the same IDN mailbox twice,
+    // the newer of the two confirmed.
+    sqlx::query!(
+        r#"INSERT INTO subscriptions
+            (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES
+            (gen_random_uuid(), 'ursula@Bücher.example', 'le guin',
+                now() - interval '2 days', 'pending_confirmation', 'old'),
+            (gen_random_uuid(), 'ursula@bücher.EXAMPLE', 'le guin',
+                now() - interval '1 day', 'confirmed', 'new')"#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    backfill_canonical_emails(&app.db_pool, app.email_normalization)
+        .await
+        .unwrap();
+
+    let saved = sqlx::query!(
+        r#"SELECT email_canonical AS "email_canonical!", status
+        FROM subscriptions"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email_canonical, "ursula@xn--bcher-kva.example");
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_backfill_keeps_an_old_confirmed_row_over_a_new_subscription() {
+    let app = spawn_app().await;
+    // Confirmed before `email_canonical` existed.
+    sqlx::query!(
+        r#"INSERT INTO subscriptions
+            (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES
+            (gen_random_uuid(), 'Ursula@Example.com', 'le guin',
+                now() - interval '1 year', 'confirmed', 'old')"#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Signed up again after startup, before the backfill reached the row.
+    app.post_subscriptions("name=le%20guin&email=Ursula%40example.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    backfill_canonical_emails(&app.db_pool, app.email_normalization)
+        .await
+        .unwrap();
+
+    let saved = sqlx::query!(
+        r#"SELECT email, email_canonical AS "email_canonical!", status
+        FROM subscriptions"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula@Example.com");
+    assert_eq!(saved[0].status, "confirmed");
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     let app = spawn_app().await;
@@ -85,6 +229,62 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_empty() {
     }
 }
 
+#[tokio::test]
+async fn subscribe_validation_errors_carry_field_details() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=dude&email=not-a-valid-email".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "validation_error");
+    assert_eq!(body["details"][0]["field"], "email");
+    assert!(body["details"][0]["message"].is_string());
+    assert!(uuid::Uuid::parse_str(body["request_id"].as_str().unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn subscribe_reports_every_invalid_field_at_once() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscriptions("name=%3Cscript%3E&email=ursula%40".into())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["details"],
+        serde_json::json!([
+            {
+                "field": "name",
+                "code": "forbidden_character",
+                "message": "The name contains the forbidden character '<'."
+            },
+            {
+                "field": "email",
+                "code": "missing_domain",
+                "message": "The email address has no domain."
+            }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn malformed_subscribe_forms_are_rejected_with_the_error_envelope() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscriptions("name=le%20guin".into()).await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_request");
+    assert!(body["message"].is_string());
+    assert!(body["request_id"].is_string());
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let app = spawn_app().await;
@@ -98,6 +298,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
         .await;
 
     let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     assert_eq!(200, response.status().as_u16());
 }
@@ -114,6 +315,7 @@ async fn subscribe_sends_link_in_confirmation_email() {
         .await;
 
     let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -126,14 +328,245 @@ async fn subscribe_fails_if_there_is_a_fatal_db_error() {
     let app = spawn_app().await;
     let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
 
-    sqlx::query!(
-        "ALTER TABLE subscription_tokens DROP COLUMN subscription_token",
-    )
-    .execute(&app.db_pool)
-    .await
-    .unwrap();
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN token_hash",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
 
     let response = app.post_subscriptions(body.into()).await;
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_returns_a_200_and_resends_the_confirmation_email() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    // The two confirmation emails go out one by one or in a batch,
+    // depending on whether they are due together when the worker polls.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 0, "Message": "OK" },
+            ]),
+        ))
+        .mount(&app.email_server)
+        .await;
+
+    let first = app.post_subscriptions(body.into()).await;
+    let second = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    let sent: usize = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| match r.url.path() {
+            "/email/batch" => {
+                r.body_json::<Vec<serde_json::Value>>().unwrap().len()
+            }
+            _ => 1,
+        })
+        .sum();
+    assert_eq!(sent, 2);
+}
+
+#[tokio::test]
+async fn concurrent_subscriptions_for_one_address_both_succeed() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    for _ in 0..5 {
+        let (first, second) = tokio::join!(
+            app.post_subscriptions(body.into()),
+            app.post_subscriptions(body.into()),
+        );
+        assert_eq!(200, first.status().as_u16());
+        assert_eq!(200, second.status().as_u16());
+    }
+
+    let saved =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_sends_a_new_link_and_keeps_the_old_one(
+) {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "pending_confirmation");
+
+    let response = reqwest::get(first_links.html).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscription_tokens_are_stored_as_hashes() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_links(email_request).html;
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap();
+    assert_eq!(token.len(), 43);
+    let stored = sqlx::query!("SELECT token_hash FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(stored.token_hash, token);
+    assert_eq!(stored.token_hash, hash_subscription_token(&token));
+}
+
+#[tokio::test]
+async fn subscribing_with_a_confirmed_email_returns_a_200_without_sending_an_email(
+) {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    // The confirmation and welcome emails, and nothing for the second call.
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_the_token_expired_issues_a_fresh_token() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    app.expire_subscription_tokens().await;
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_200_even_if_the_email_provider_is_down() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+async fn subscribe_does_not_queue_an_email_if_the_transaction_fails() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN token_hash",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    app.post_subscriptions(body.into()).await;
+
+    let queued =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(queued, 0);
+}
@@ -1,7 +1,9 @@
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{
+    assert_is_redirect_to, spawn_app, spawn_app_with, TestApp,
+};
 
 #[tokio::test]
 pub async fn missing_tokens_are_rejected_with_a_400() {
@@ -15,6 +17,28 @@ pub async fn missing_tokens_are_rejected_with_a_400() {
     assert_eq!(response.status().as_u16(), 400);
 }
 
+#[tokio::test]
+pub async fn unknown_tokens_are_rejected_with_the_error_envelope() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/subscriptions/confirm?subscription_token=nonexistent",
+            app.address
+        ))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_token");
+    assert_eq!(body["message"], "Invalid token.");
+    assert!(body["request_id"].is_string());
+    assert!(body.get("details").is_none());
+}
+
 #[tokio::test]
 pub async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     let app = spawn_app().await;
@@ -27,6 +51,7 @@ pub async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -46,6 +71,7 @@ pub async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .await;
 
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
@@ -86,12 +112,10 @@ pub async fn confirmation_fails_if_there_is_a_fatal_db_error_retrieving_token()
     let app = spawn_app().await;
     let token = 123;
 
-    sqlx::query!(
-        "ALTER TABLE subscription_tokens DROP COLUMN subscription_token",
-    )
-    .execute(&app.db_pool)
-    .await
-    .unwrap();
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN token_hash",)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
 
     let response = reqwest::get(&format!(
         "{}/subscriptions/confirm?subscription_token={}",
@@ -109,6 +133,7 @@ pub async fn confirmation_fails_if_there_is_a_fatal_db_error_upgrading_subscript
 
     let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
@@ -121,3 +146,450 @@ pub async fn confirmation_fails_if_there_is_a_fatal_db_error_upgrading_subscript
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+pub async fn expired_tokens_are_rejected_with_a_410() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.expire_subscription_tokens().await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+pub async fn resending_after_expiry_issues_a_fresh_working_link() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    app.expire_subscription_tokens().await;
+
+    let response = app
+        .post_resend_confirmation("email=test_user%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let expired_links = app.get_confirmation_links(&email_requests[0]);
+    let fresh_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(expired_links.html, fresh_links.html);
+
+    let response = reqwest::get(fresh_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+pub async fn resending_for_an_unknown_email_returns_a_200_without_sending() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_resend_confirmation("email=nobody%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+pub async fn resending_to_a_suppressed_address_returns_a_200_without_queuing() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("test_user@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    app.post_suppression(serde_json::json!({
+        "email": "test_user@gmail.com",
+        "reason": "manual",
+    }))
+    .await;
+
+    let response = app
+        .post_resend_confirmation("email=test_user%40gmail.com".into())
+        .await;
+
+    // Answered as for any other address, and nothing is queued for it.
+    assert_eq!(response.status().as_u16(), 200);
+    let messages =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM email_messages"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(messages.count, 1);
+}
+
+#[tokio::test]
+pub async fn resending_to_a_blocked_domain_is_rejected() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("ursula@example.org")
+        .await;
+    app.test_user.login(&app).await;
+    app.post_email_domain_rule(serde_json::json!({
+        "domain": "example.org",
+        "rule": "block",
+    }))
+    .await;
+
+    let response = app
+        .post_resend_confirmation("email=ursula%40example.org".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["details"][0]["code"], "blocked_domain");
+}
+
+#[tokio::test]
+pub async fn clicking_the_link_twice_reports_the_subscription_as_already_confirmed(
+) {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    let first = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    assert_eq!(first.status().as_u16(), 200);
+    assert!(first
+        .text()
+        .await
+        .unwrap()
+        .contains("Thanks for confirming"));
+    let second = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(second.status().as_u16(), 200);
+    assert!(second.text().await.unwrap().contains("already confirmed"));
+    let used = sqlx::query!(
+        "SELECT used_at IS NOT NULL AS \"used!\" FROM subscription_tokens"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(used.used);
+    assert!(app
+        .get_metrics()
+        .await
+        .contains("subscriptions_confirmed_total 1"));
+}
+
+#[tokio::test]
+pub async fn a_used_link_does_not_resubscribe_someone_who_unsubscribed() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+    app.post_unsubscribe(&token).await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+pub async fn an_unused_link_is_rejected_once_the_subscriber_unsubscribed() {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+    app.post_unsubscribe(&token).await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+pub async fn a_link_used_before_unsubscribing_cannot_confirm_a_new_subscription(
+) {
+    let app = spawn_app().await;
+    let body = "name=BoatyMcBoatFace&email=test_user%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let old_links = app.get_confirmation_links(email_request);
+    reqwest::get(old_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+    app.post_unsubscribe(&token).await;
+    app.post_subscriptions(body.into()).await;
+
+    let response = reqwest::get(old_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+pub async fn an_invalid_stored_email_confirms_without_a_welcome_email() {
+    let app = spawn_app().await;
+    let link = confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscriptions SET email = 'not-an-email'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    let queued = sqlx::query!(
+        r#"SELECT count(*) AS "count!" FROM email_messages
+        WHERE template = 'welcome'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+async fn confirmation_link(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(
+        "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
+
+fn assert_is_html(response: &reqwest::Response) {
+    let content_type = response.headers()["Content-Type"].to_str().unwrap();
+    assert!(content_type.starts_with("text/html"));
+}
+
+#[tokio::test]
+pub async fn following_the_link_renders_a_confirmation_page() {
+    let app = spawn_app().await;
+    let link = confirmation_link(&app).await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_is_html(&response);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("<title>Subscription confirmed</title>"));
+}
+
+#[tokio::test]
+pub async fn an_expired_link_renders_a_page_offering_a_new_one() {
+    let app = spawn_app().await;
+    let link = confirmation_link(&app).await;
+    app.expire_subscription_tokens().await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    assert_is_html(&response);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"action="/subscriptions/confirm/resend""#));
+}
+
+#[tokio::test]
+pub async fn submitting_the_expired_link_form_renders_a_check_your_inbox_page()
+{
+    let app = spawn_app().await;
+    confirmation_link(&app).await;
+    app.expire_subscription_tokens().await;
+
+    // As a browser submits the form on the expired link page.
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions/confirm/resend", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .body("email=test_user%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_is_html(&response);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("<title>Check your inbox</title>"));
+}
+
+#[tokio::test]
+pub async fn resending_for_a_json_client_returns_an_empty_200() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions/confirm/resend", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "application/json")
+        .body("email=nobody%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+pub async fn an_unknown_link_renders_the_invalid_link_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=nonexistent",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_is_html(&response);
+    let page = response.text().await.unwrap();
+    assert!(page.contains("not valid"));
+}
+
+#[tokio::test]
+pub async fn outcomes_with_a_configured_url_redirect_there() {
+    let app = spawn_app_with(|c| {
+        c.application.confirmation_redirects.confirmed =
+            Some("https://example.com/welcome".into());
+        c.application.confirmation_redirects.invalid =
+            Some("https://example.com/oops".into());
+    })
+    .await;
+    let link = confirmation_link(&app).await;
+
+    let confirmed = app.api_client.get(link.clone()).send().await.unwrap();
+    // No URL configured for this outcome, so the page is rendered.
+    let already_confirmed = app.api_client.get(link).send().await.unwrap();
+    let invalid = app
+        .api_client
+        .get(format!(
+            "{}/subscriptions/confirm?subscription_token=nonexistent",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&confirmed, "https://example.com/welcome");
+    assert_eq!(already_confirmed.status().as_u16(), 200);
+    assert_is_redirect_to(&invalid, "https://example.com/oops");
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "confirmed"
+    );
+}
+
+#[tokio::test]
+pub async fn pages_can_be_replaced_from_the_templates_directory() {
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(dir.join("pages/confirmation")).unwrap();
+    std::fs::write(
+        dir.join("pages/confirmation/confirmed.html"),
+        r#"{% extends "pages/layout.html" %}
+{% block content %}Welcome to the club!{% endblock %}"#,
+    )
+    .unwrap();
+    let app = spawn_app_with(|c| {
+        c.application.templates_dir = Some(dir.clone());
+    })
+    .await;
+    let link = confirmation_link(&app).await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Welcome to the club!"));
+    assert!(page.starts_with("<!DOCTYPE html>"));
+}
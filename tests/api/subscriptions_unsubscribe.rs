@@ -0,0 +1,199 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+/// Publish an issue to the confirmed subscriber `email` and return the
+/// `List-Unsubscribe` URL it was sent with.
+async fn list_unsubscribe_url(app: &TestApp, email: &str) -> reqwest::Url {
+    app.create_confirmed_subscriber(email).await;
+    app.test_user.login(app).await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "html": "<p>Newsletter body as HTML</p>" },
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_list_unsubscribe_url(&email_request)
+}
+
+#[tokio::test]
+pub async fn missing_tokens_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+pub async fn unmatched_tokens_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let get_response = app.get_unsubscribe_form("not-a-token").await;
+    let post_response = app.post_unsubscribe("not-a-token").await;
+
+    assert_eq!(get_response.status().as_u16(), 401);
+    assert_eq!(post_response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+pub async fn the_unsubscribe_page_does_not_change_the_subscriber_status() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+
+    let response = app.get_unsubscribe_form(&token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(&token));
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "confirmed"
+    );
+}
+
+#[tokio::test]
+pub async fn posting_the_unsubscribe_token_unsubscribes_a_confirmed_subscriber()
+{
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+
+    let response = app.post_unsubscribe(&token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+pub async fn unsubscribing_twice_returns_a_200() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+
+    app.post_unsubscribe(&token).await;
+    let response = app.post_unsubscribe(&token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+pub async fn subscribing_after_unsubscribing_requires_confirmation_again() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+    let token = app.get_unsubscribe_token("test_user@gmail.com").await;
+    app.post_unsubscribe(&token).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(
+            "name=BoatyMcBoatFace&email=test_user%40gmail.com".into(),
+        )
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "pending_confirmation"
+    );
+}
+
+#[tokio::test]
+pub async fn the_status_column_rejects_unknown_statuses() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("test_user@gmail.com").await;
+
+    let result = sqlx::query!("UPDATE subscriptions SET status = 'deleted'")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+pub async fn posting_to_the_list_unsubscribe_url_unsubscribes_without_a_session(
+) {
+    let app = spawn_app().await;
+    let url = list_unsubscribe_url(&app, "test_user@gmail.com").await;
+
+    let response = app.post_one_click_unsubscribe(url).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "unsubscribed"
+    );
+}
+
+#[tokio::test]
+pub async fn opening_the_list_unsubscribe_url_does_not_change_the_status() {
+    let app = spawn_app().await;
+    let url = list_unsubscribe_url(&app, "test_user@gmail.com").await;
+
+    let response = reqwest::get(url).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"action="/subscriptions/unsubscribe/one-click"#));
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "confirmed"
+    );
+}
+
+#[tokio::test]
+pub async fn one_click_unsubscribes_with_a_tampered_signature_are_rejected() {
+    let app = spawn_app().await;
+    let mut url = list_unsubscribe_url(&app, "test_user@gmail.com").await;
+    let subscriber_id: String = url
+        .query_pairs()
+        .find(|(key, _)| key == "subscriber_id")
+        .unwrap()
+        .1
+        .into_owned();
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &subscriber_id)
+        .append_pair("signature", &"0".repeat(64));
+
+    let response = app.post_one_click_unsubscribe(url).await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        app.get_subscriber_status("test_user@gmail.com").await,
+        "confirmed"
+    );
+}
@@ -0,0 +1,227 @@
+use secrecy::ExposeSecret;
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+const MESSAGE_ID: &str = "b7bc2f4a-e38e-4336-af7d-e6c392c2f817";
+
+fn bounce(kind: &str) -> serde_json::Value {
+    json!({
+        "RecordType": "Bounce",
+        "Type": kind,
+        "TypeCode": 1,
+        "MessageID": MESSAGE_ID,
+        "Email": EMAIL,
+        "BouncedAt": "2026-10-18T12:00:00Z",
+    })
+}
+
+fn spam_complaint() -> serde_json::Value {
+    json!({
+        "RecordType": "SpamComplaint",
+        "MessageID": MESSAGE_ID,
+        "Email": EMAIL,
+    })
+}
+
+/// Subscribe `EMAIL` and deliver the confirmation, which Postmark answers
+/// with `MESSAGE_ID`.
+async fn send_confirmation_email(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ErrorCode": 0,
+            "Message": "OK",
+            "MessageID": MESSAGE_ID,
+        })))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(format!("name=le%20guin&email={}", EMAIL))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn message_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM email_messages")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+async fn suppression_reason(app: &TestApp) -> Option<String> {
+    sqlx::query!(
+        "SELECT reason FROM suppressions WHERE email_canonical = $1",
+        EMAIL
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| r.reason)
+}
+
+#[tokio::test]
+async fn requests_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    let url = format!("{}/webhooks/postmark", app.address);
+
+    let anonymous = app
+        .api_client
+        .post(&url)
+        .json(&bounce("HardBounce"))
+        .send()
+        .await
+        .unwrap();
+    let wrong_password = app
+        .api_client
+        .post(&url)
+        .basic_auth(&app.webhooks.username, Some("not-the-password"))
+        .json(&bounce("HardBounce"))
+        .send()
+        .await
+        .unwrap();
+    let wrong_username = app
+        .api_client
+        .post(&url)
+        .basic_auth(
+            "not-the-username",
+            Some(app.webhooks.password.expose_secret()),
+        )
+        .json(&bounce("HardBounce"))
+        .send()
+        .await
+        .unwrap();
+
+    for response in [anonymous, wrong_password, wrong_username] {
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            response.headers()["WWW-Authenticate"],
+            r#"Basic realm="webhooks""#
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["code"], "unauthorized");
+        assert!(body["request_id"].is_string());
+    }
+    assert_eq!(suppression_reason(&app).await, None);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_suppresses_the_address_and_marks_the_subscriber() {
+    let app = spawn_app().await;
+    send_confirmation_email(&app).await;
+
+    let response = app.post_postmark_webhook(&bounce("HardBounce")).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.get_subscriber_status(EMAIL).await, "bounced");
+    assert_eq!(suppression_reason(&app).await.as_deref(), Some("bounce"));
+    assert_eq!(message_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn a_spam_complaint_suppresses_the_address_and_marks_the_subscriber() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(EMAIL).await;
+
+    let response = app.post_postmark_webhook(&spam_complaint()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.get_subscriber_status(EMAIL).await, "complained");
+    assert_eq!(suppression_reason(&app).await.as_deref(), Some("complaint"));
+}
+
+#[tokio::test]
+async fn a_soft_bounce_changes_nothing() {
+    let app = spawn_app().await;
+    send_confirmation_email(&app).await;
+
+    let response = app.post_postmark_webhook(&bounce("SoftBounce")).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.get_subscriber_status(EMAIL).await,
+        "pending_confirmation"
+    );
+    assert_eq!(suppression_reason(&app).await, None);
+    assert_eq!(message_status(&app).await, "sent");
+}
+
+#[tokio::test]
+async fn a_delivery_is_recorded_on_the_message() {
+    let app = spawn_app().await;
+    send_confirmation_email(&app).await;
+
+    let response = app
+        .post_postmark_webhook(&json!({
+            "RecordType": "Delivery",
+            "MessageID": MESSAGE_ID,
+            "Recipient": EMAIL,
+            "DeliveredAt": "2026-10-18T12:00:00Z",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(message_status(&app).await, "delivered");
+}
+
+#[tokio::test]
+async fn a_late_delivery_does_not_hide_a_bounce() {
+    let app = spawn_app().await;
+    send_confirmation_email(&app).await;
+    app.post_postmark_webhook(&bounce("HardBounce")).await;
+
+    app.post_postmark_webhook(&json!({
+        "RecordType": "Delivery",
+        "MessageID": MESSAGE_ID,
+        "Recipient": EMAIL,
+    }))
+    .await;
+
+    assert_eq!(message_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn other_record_types_are_acknowledged_and_ignored() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&json!({
+            "RecordType": "Open",
+            "MessageID": MESSAGE_ID,
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn queued_emails_to_a_suppressed_address_are_not_sent() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(format!("name=le%20guin&email={}", EMAIL))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_postmark_webhook(&spam_complaint()).await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(message_status(&app).await, "suppressed");
+    let queued =
+        sqlx::query!(r#"SELECT count(*) AS "count!" FROM delivery_queue"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count;
+    assert_eq!(queued, 0);
+}